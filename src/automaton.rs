@@ -0,0 +1,116 @@
+use std::cmp;
+
+/// A state machine reading keys byte by byte, to search a trie with
+/// `Trie::search()`.
+///
+/// The bytes shared by all the keys of a subtree are read once for the whole
+/// subtree, which is skipped as soon as `can_match()` returns `false`. Where
+/// `next_bytes()` restricts the bytes that can be read next, only the children
+/// holding these bytes are visited.
+pub trait Automaton {
+    /// The state of the automaton after reading some bytes.
+    type State: Clone;
+
+    /// Returns the state before any byte has been read.
+    fn start(&self) -> Self::State;
+
+    /// Returns the state reached from `state` by reading `byte`.
+    fn transition(&self, state: &Self::State, byte: u8) -> Self::State;
+
+    /// Returns `true` if the bytes read so far form an accepted key.
+    fn is_match(&self, state: &Self::State) -> bool;
+
+    /// Returns `true` if the bytes read so far may be followed by more bytes
+    /// forming an accepted key, or are accepted themselves.
+    fn can_match(&self, _state: &Self::State) -> bool {
+        true
+    }
+
+    /// Returns the only bytes that may be read next from `state` for the
+    /// automaton to match, or `None` if any byte may be.
+    fn next_bytes(&self, _state: &Self::State) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+impl<A: ?Sized + Automaton> Automaton for &A {
+    type State = A::State;
+
+    #[inline]
+    fn start(&self) -> Self::State {
+        (**self).start()
+    }
+
+    #[inline]
+    fn transition(&self, state: &Self::State, byte: u8) -> Self::State {
+        (**self).transition(state, byte)
+    }
+
+    #[inline]
+    fn is_match(&self, state: &Self::State) -> bool {
+        (**self).is_match(state)
+    }
+
+    #[inline]
+    fn can_match(&self, state: &Self::State) -> bool {
+        (**self).can_match(state)
+    }
+
+    #[inline]
+    fn next_bytes(&self, state: &Self::State) -> Option<Vec<u8>> {
+        (**self).next_bytes(state)
+    }
+}
+
+/// An automaton accepting the keys within an edit distance of a query.
+///
+/// Its state is the last row of the Levenshtein matrix between the bytes read
+/// so far and the query.
+#[derive(Clone, Debug)]
+pub(crate) struct Levenshtein<'q> {
+    query: &'q [u8],
+    max_distance: usize,
+}
+
+impl<'q> Levenshtein<'q> {
+    pub fn new(query: &'q [u8], max_distance: usize) -> Self {
+        Levenshtein {
+            query,
+            max_distance,
+        }
+    }
+
+    /// Returns the distance between the bytes read so far and the query.
+    #[inline]
+    pub fn distance(&self, row: &[usize]) -> usize {
+        row[self.query.len()]
+    }
+}
+
+impl<'q> Automaton for Levenshtein<'q> {
+    type State = Vec<usize>;
+
+    fn start(&self) -> Self::State {
+        (0..=self.query.len()).collect()
+    }
+
+    fn transition(&self, row: &Self::State, byte: u8) -> Self::State {
+        let mut next = Vec::with_capacity(row.len());
+        next.push(row[0] + 1);
+        for (i, &query_byte) in self.query.iter().enumerate() {
+            let substitution = row[i] + (query_byte != byte) as usize;
+            next.push(cmp::min(cmp::min(row[i + 1], next[i]) + 1, substitution));
+        }
+        next
+    }
+
+    #[inline]
+    fn is_match(&self, row: &Self::State) -> bool {
+        self.distance(row) <= self.max_distance
+    }
+
+    #[inline]
+    fn can_match(&self, row: &Self::State) -> bool {
+        row.iter().any(|&distance| distance <= self.max_distance)
+    }
+}
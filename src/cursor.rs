@@ -0,0 +1,523 @@
+use std::marker::PhantomData;
+
+use super::error::{InsertError, InsertErrorKind};
+use super::node::{Ancestors, Node};
+use super::trie::InsertPosition;
+use super::Trie;
+
+/// A reference to a node, through which a cursor walks down the trie.
+trait NodeRef: Copy {
+    type Key: PartialEq + AsRef<[u8]>;
+    type Value;
+
+    fn node(&self) -> &Node<Self::Key, Self::Value>;
+
+    /// Returns the child of this internal node holding `nibble`.
+    fn child(self, nibble: usize) -> Self;
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> NodeRef for &Node<TK, TV> {
+    type Key = TK;
+    type Value = TV;
+
+    #[inline]
+    fn node(&self) -> &Node<TK, TV> {
+        self
+    }
+
+    #[inline]
+    fn child(self, nibble: usize) -> Self {
+        self.as_internal().nibbles.get(nibble).unwrap()
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> NodeRef for *mut Node<TK, TV> {
+    type Key = TK;
+    type Value = TV;
+
+    #[inline]
+    fn node(&self) -> &Node<TK, TV> {
+        unsafe { &**self }
+    }
+
+    #[inline]
+    fn child(self, nibble: usize) -> Self {
+        unsafe { (*self).as_mut_internal().nibbles.get_mut(nibble).unwrap() }
+    }
+}
+
+/// The position of a cursor.
+#[derive(Clone)]
+struct Position<P: NodeRef> {
+    /// The internal nodes from the root to the current leaf, each with the
+    /// nibble of the child leading to the leaf.
+    path: Vec<(P, usize)>,
+    /// The current leaf, or `None` if the cursor is before the first entry
+    /// or after the last one.
+    leaf: Option<P>,
+    /// Whether the cursor is after the last entry rather than before the
+    /// first one, when there is no current leaf.
+    after_end: bool,
+}
+
+impl<P: NodeRef> Position<P> {
+    fn new() -> Self {
+        Position {
+            path: vec![],
+            leaf: None,
+            after_end: false,
+        }
+    }
+
+    /// Moves to the first leaf of the subtree `t`, or to its last leaf if
+    /// `last` is `true`.
+    fn descend(&mut self, mut t: P, last: bool) {
+        loop {
+            let nibble = match *t.node() {
+                Node::Internal(ref internal) if last => internal.nibbles.tail_index(),
+                Node::Internal(ref internal) => internal.nibbles.head_index(),
+                _ => break,
+            };
+            self.path.push((t, nibble));
+            t = t.child(nibble);
+        }
+        self.leaf = Some(t);
+    }
+
+    /// Moves to the first leaf following the subtree at the end of the path,
+    /// or to the last leaf preceding it if `forward` is `false`.
+    fn ascend(&mut self, forward: bool) {
+        while let Some((t, nibble)) = self.path.pop() {
+            let nibbles = &t.node().as_internal().nibbles;
+            let sibling = if forward {
+                nibbles.next_index(nibble + 1)
+            } else {
+                nibbles.prev_index(nibble)
+            };
+            if let Some(sibling) = sibling {
+                self.path.push((t, sibling));
+                return self.descend(t.child(sibling), !forward);
+            }
+        }
+        self.leaf = None;
+        self.after_end = forward;
+    }
+
+    /// Moves to the next leaf, or to the previous one if `forward` is
+    /// `false`.
+    fn step(&mut self, root: Option<P>, forward: bool) {
+        if self.leaf.is_some() {
+            return self.ascend(forward);
+        }
+        if self.after_end == forward {
+            return;
+        }
+        match root {
+            None => self.after_end = forward,
+            Some(root) => {
+                self.path.clear();
+                self.descend(root, !forward);
+            }
+        }
+    }
+
+    /// Moves to the leaf with the smallest key greater than or equal to
+    /// `key`, or after the last leaf if there is none.
+    fn seek(&mut self, root: Option<P>, key: &[u8]) {
+        self.path.clear();
+        let root = match root {
+            None => {
+                self.leaf = None;
+                self.after_end = true;
+                return;
+            }
+            Some(root) => root,
+        };
+        let (critical, below) = {
+            let (closest, _) = Trie::find_closest_leaf(root.node(), key);
+            let closest_key = closest.key.as_ref();
+            match Trie::<P::Key, P::Value>::critical_index(key, closest_key) {
+                None => (usize::MAX, false),
+                Some(critical) => (
+                    critical,
+                    Trie::<P::Key, P::Value>::nibble(key, critical)
+                        < Trie::<P::Key, P::Value>::nibble(closest_key, critical),
+                ),
+            }
+        };
+        let mut t = root;
+        loop {
+            let (nibble, next) = match *t.node() {
+                Node::Internal(ref internal) if internal.index <= critical => {
+                    let nibble = Trie::<P::Key, P::Value>::nibble(key, internal.index);
+                    (nibble, internal.nibbles.next_index(nibble))
+                }
+                _ => break,
+            };
+            match next {
+                Some(next) if next == nibble => {
+                    self.path.push((t, nibble));
+                    t = t.child(nibble);
+                }
+                // The key diverges from the trie at this node.
+                Some(next) => {
+                    self.path.push((t, next));
+                    return self.descend(t.child(next), false);
+                }
+                None => {
+                    self.descend(t, true);
+                    return self.ascend(true);
+                }
+            }
+        }
+        // All the keys of `t` are either lower or greater than `key`, unless
+        // `t` is the leaf holding `key`.
+        if critical == usize::MAX {
+            self.leaf = Some(t);
+        } else if below {
+            self.descend(t, false);
+        } else {
+            self.descend(t, true);
+            self.ascend(true);
+        }
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> Position<*mut Node<TK, TV>> {
+    /// Locates the place where `key` has to be attached in `trie`, given that
+    /// the current leaf shares the longest prefix with it, and returns it
+    /// along with the depth of the node it is attached to or moves.
+    fn insert_position(
+        &self,
+        trie: *mut Trie<TK, TV>,
+        key: &[u8],
+    ) -> (InsertPosition<TK, TV>, usize) {
+        let leaf = match self.leaf {
+            None => return (InsertPosition::Root, 0),
+            Some(leaf) => leaf,
+        };
+        let leaf_key = leaf.node().as_leaf().key.as_ref();
+        let critical = match Trie::<TK, TV>::critical_index(key, leaf_key) {
+            None => unsafe { debug_unreachable!() },
+            Some(critical) => critical,
+        };
+        let orig_nibble = Trie::<TK, TV>::nibble(leaf_key, critical);
+        // The height is checked against the same leaf as in `try_insert()`,
+        // so that both refuse the same keys.
+        let root = self.path.first().map_or(leaf, |&(t, _)| t);
+        let (_, height) = Trie::find_closest_leaf(root.node(), key);
+        let mut ancestors = Ancestors::new();
+        for (depth, &(t, _)) in self.path.iter().enumerate() {
+            let index = t.node().as_internal().index;
+            if index > critical {
+                let position =
+                    Trie::split_position(trie, t, critical, orig_nibble, height, ancestors);
+                return (position, depth);
+            }
+            ancestors.push(t);
+            if index == critical {
+                let nibble = Trie::<TK, TV>::nibble(key, critical);
+                return (InsertPosition::Child(t, nibble, ancestors), depth);
+            }
+        }
+        let position = Trie::split_position(trie, leaf, critical, orig_nibble, height, ancestors);
+        (position, self.path.len())
+    }
+
+    /// Updates the path after a leaf was inserted at the node `t`, found at
+    /// `depth` on the path of the closest leaf. `t` either got a new child, or
+    /// was moved under a new internal node, in the slot for `split`.
+    ///
+    /// The nodes below `t` may have moved, and are located again.
+    fn relink(&mut self, depth: usize, t: *mut Node<TK, TV>, split: Option<usize>) {
+        let on_path = match self.path.get(depth) {
+            Some(&(node, _)) => node == t,
+            None => depth == self.path.len() && self.leaf == Some(t),
+        };
+        if !on_path {
+            return;
+        }
+        if let Some(nibble) = split {
+            self.path.insert(depth, (t, nibble));
+        }
+        for i in depth + 1..self.path.len() {
+            let (parent, nibble) = self.path[i - 1];
+            self.path[i].0 = parent.child(nibble);
+        }
+        if let Some(&(parent, nibble)) = self.path.last() {
+            self.leaf = Some(parent.child(nibble));
+        }
+    }
+}
+
+/// A cursor over the entries of a trie, moving in both directions in
+/// lexicographic order of the keys.
+///
+/// A cursor is either on an entry, or before the first entry, or after the
+/// last one. It starts before the first entry, and iterating over it moves it
+/// forward, starting with the first entry.
+pub struct Cursor<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    root: Option<&'t Node<TK, TV>>,
+    position: Position<&'t Node<TK, TV>>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Cursor<'t, TK, TV> {
+    pub(crate) fn new(trie: &'t Trie<TK, TV>) -> Self {
+        Cursor {
+            root: trie.root(),
+            position: Position::new(),
+        }
+    }
+
+    /// Moves the cursor to the entry with the smallest key greater than or
+    /// equal to `key`, or after the last entry if there is none.
+    pub fn seek<Q: ?Sized + AsRef<[u8]>>(&mut self, key: &Q) {
+        self.position.seek(self.root, key.as_ref())
+    }
+
+    /// Moves the cursor to the previous entry, and returns it.
+    ///
+    /// Returns `None` once the cursor moves before the first entry.
+    pub fn prev(&mut self) -> Option<(&'t TK, &'t TV)> {
+        self.position.step(self.root, false);
+        self.key_value()
+    }
+
+    /// Returns the key of the current entry.
+    pub fn key(&self) -> Option<&'t TK> {
+        self.position.leaf.map(|leaf| &leaf.as_leaf().key)
+    }
+
+    /// Returns the value of the current entry.
+    pub fn value(&self) -> Option<&'t TV> {
+        self.position.leaf.map(|leaf| &leaf.as_leaf().val)
+    }
+
+    /// Returns the key and the value of the current entry.
+    pub fn key_value(&self) -> Option<(&'t TK, &'t TV)> {
+        self.position.leaf.map(|leaf| {
+            let leaf = leaf.as_leaf();
+            (&leaf.key, &leaf.val)
+        })
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for Cursor<'t, TK, TV> {
+    type Item = (&'t TK, &'t TV);
+
+    /// Moves the cursor to the next entry, and returns it.
+    ///
+    /// Returns `None` once the cursor moves after the last entry.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.position.step(self.root, true);
+        self.key_value()
+    }
+}
+
+/// A cursor over the entries of a trie, moving in both directions in
+/// lexicographic order of the keys, and able to update and remove the current
+/// entry, and to insert new entries next to it.
+///
+/// A cursor is either on an entry, or before the first entry, or after the
+/// last one. It starts before the first entry.
+pub struct CursorMut<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    trie: *mut Trie<TK, TV>,
+    /// The root of the trie, derived once so that the path to the current
+    /// leaf remains valid.
+    root: Option<*mut Node<TK, TV>>,
+    position: Position<*mut Node<TK, TV>>,
+    marker: PhantomData<&'t mut Trie<TK, TV>>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> CursorMut<'t, TK, TV> {
+    pub(crate) fn new(trie: &'t mut Trie<TK, TV>) -> Self {
+        let mut cursor = CursorMut {
+            trie,
+            root: None,
+            position: Position::new(),
+            marker: PhantomData,
+        };
+        cursor.locate_root();
+        cursor
+    }
+
+    /// Derives the root again, after it was added or removed.
+    #[inline]
+    fn locate_root(&mut self) {
+        self.root = unsafe { (*self.trie).root_mut().map(|root| root as *mut _) };
+    }
+
+    /// Moves the cursor to the entry with the smallest key greater than or
+    /// equal to `key`, or after the last entry if there is none.
+    pub fn seek<Q: ?Sized + AsRef<[u8]>>(&mut self, key: &Q) {
+        self.position.seek(self.root, key.as_ref())
+    }
+
+    /// Moves the cursor to the next entry, and returns it.
+    ///
+    /// Returns `None` once the cursor moves after the last entry.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(&TK, &mut TV)> {
+        self.position.step(self.root, true);
+        self.key_value_mut()
+    }
+
+    /// Moves the cursor to the previous entry, and returns it.
+    ///
+    /// Returns `None` once the cursor moves before the first entry.
+    pub fn prev(&mut self) -> Option<(&TK, &mut TV)> {
+        self.position.step(self.root, false);
+        self.key_value_mut()
+    }
+
+    /// Returns the key of the current entry.
+    pub fn key(&self) -> Option<&TK> {
+        self.position
+            .leaf
+            .map(|leaf| unsafe { &(*leaf).as_leaf().key })
+    }
+
+    /// Returns the value of the current entry.
+    pub fn value(&self) -> Option<&TV> {
+        self.position
+            .leaf
+            .map(|leaf| unsafe { &(*leaf).as_leaf().val })
+    }
+
+    /// Returns a mutable reference to the value of the current entry.
+    pub fn value_mut(&mut self) -> Option<&mut TV> {
+        let leaf = self.position.leaf?;
+        Trie::mark_scores_stale(self.trie);
+        Some(unsafe { &mut (*leaf).as_mut_leaf().val })
+    }
+
+    /// Returns the key and a mutable reference to the value of the current
+    /// entry.
+    pub fn key_value_mut(&mut self) -> Option<(&TK, &mut TV)> {
+        let leaf = self.position.leaf?;
+        Trie::mark_scores_stale(self.trie);
+        let leaf = unsafe { (*leaf).as_mut_leaf() };
+        Some((&leaf.key, &mut leaf.val))
+    }
+
+    /// Removes the current entry from the trie, and returns it.
+    ///
+    /// The cursor moves to the next entry.
+    pub fn remove_current(&mut self) -> Option<(TK, TV)> {
+        self.position.leaf?;
+        let mut ancestors = Ancestors::new();
+        for &(t, _) in &self.position.path {
+            ancestors.push(t);
+        }
+        let leaf = match self.position.path.pop() {
+            None => {
+                self.position.leaf = None;
+                self.position.after_end = true;
+                let leaf = Trie::unlink_leaf(self.trie, None, ancestors);
+                self.root = None;
+                leaf
+            }
+            Some((parent, nibble)) => {
+                let (len, head) = {
+                    let nibbles = &parent.node().as_internal().nibbles;
+                    (nibbles.len(), nibbles.head_index())
+                };
+                let leaf = Trie::unlink_leaf(self.trie, Some((parent, nibble)), ancestors);
+                if len == 2 {
+                    // The parent was replaced with the remaining child.
+                    if head == nibble {
+                        self.position.descend(parent, false);
+                    } else {
+                        self.position.ascend(true);
+                    }
+                } else {
+                    match parent.node().as_internal().nibbles.next_index(nibble) {
+                        Some(next) => {
+                            self.position.path.push((parent, next));
+                            self.position.descend(parent.child(next), false);
+                        }
+                        None => self.position.ascend(true),
+                    }
+                }
+                leaf
+            }
+        };
+        Some((leaf.key, leaf.val))
+    }
+
+    /// Inserts a new entry right before the current entry, or as the last
+    /// entry if the cursor is not on an entry.
+    ///
+    /// The cursor stays where it is. An error holding the key and the value
+    /// is returned if the key does not sort right before the current entry,
+    /// or if the insertion would exceed the maximum height of the trie.
+    pub fn insert_before(&mut self, key: TK, val: TV) -> Result<(), InsertError<TK, TV>> {
+        self.insert_next(key, val, false)
+    }
+
+    /// Inserts a new entry right after the current entry, or as the first
+    /// entry if the cursor is not on an entry.
+    ///
+    /// The cursor stays where it is. An error holding the key and the value
+    /// is returned if the key does not sort right after the current entry, or
+    /// if the insertion would exceed the maximum height of the trie.
+    pub fn insert_after(&mut self, key: TK, val: TV) -> Result<(), InsertError<TK, TV>> {
+        self.insert_next(key, val, true)
+    }
+
+    fn insert_next(&mut self, key: TK, val: TV, after: bool) -> Result<(), InsertError<TK, TV>> {
+        let mut neighbour = self.position.clone();
+        if neighbour.leaf.is_none() {
+            neighbour.after_end = !after;
+        }
+        neighbour.step(self.root, after);
+        let (lower, upper) = if after {
+            (self.position.leaf, neighbour.leaf)
+        } else {
+            (neighbour.leaf, self.position.leaf)
+        };
+        let fits = lower.is_none_or(|lower| lower.node().as_leaf().key.as_ref() < key.as_ref())
+            && upper.is_none_or(|upper| key.as_ref() < upper.node().as_leaf().key.as_ref());
+        if !fits {
+            return Err(InsertError {
+                key,
+                val,
+                kind: InsertErrorKind::Unordered,
+            });
+        }
+        // The closest leaf to the key is whichever of the current leaf and
+        // its neighbour shares the longest prefix with it.
+        let shared = |leaf: Option<*mut Node<TK, TV>>| {
+            leaf.map(|leaf| {
+                Trie::<TK, TV>::critical_index(key.as_ref(), leaf.node().as_leaf().key.as_ref())
+            })
+        };
+        let closest = if shared(neighbour.leaf) > shared(self.position.leaf) {
+            &neighbour
+        } else {
+            &self.position
+        };
+        let (position, depth) = closest.insert_position(self.trie, key.as_ref());
+        let t = closest
+            .path
+            .get(depth)
+            .map_or(closest.leaf, |&(t, _)| Some(t));
+        let split = match position {
+            InsertPosition::TooHigh => {
+                return Err(InsertError {
+                    key,
+                    val,
+                    kind: InsertErrorKind::MaxHeight,
+                })
+            }
+            InsertPosition::Split(_, _, orig_nibble, _) => Some(orig_nibble),
+            _ => None,
+        };
+        Trie::insert_at(self.trie, position, key, val);
+        match t {
+            None => self.locate_root(),
+            Some(t) => self.position.relink(depth, t, split),
+        }
+        Ok(())
+    }
+}
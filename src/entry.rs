@@ -0,0 +1,204 @@
+use std::marker::PhantomData;
+
+use super::error::{InsertError, InsertErrorKind};
+use super::node::{Ancestors, LeafNode};
+use super::trie::{InsertPosition, NodeParent};
+use super::Trie;
+
+/// A view into a single entry of a trie, which may either be vacant or
+/// occupied.
+///
+/// This is constructed by the `entry()` method of `Trie`.
+pub enum Entry<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    /// An occupied entry.
+    Occupied(OccupiedEntry<'t, TK, TV>),
+    /// A vacant entry.
+    Vacant(VacantEntry<'t, TK, TV>),
+}
+
+/// A view into an occupied entry of a trie.
+pub struct OccupiedEntry<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    trie: *mut Trie<TK, TV>,
+    leaf: *mut LeafNode<TK, TV>,
+    parent: NodeParent<TK, TV>,
+    ancestors: Ancestors<TK, TV>,
+    marker: PhantomData<&'t mut Trie<TK, TV>>,
+}
+
+/// A view into a vacant entry of a trie.
+pub struct VacantEntry<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    trie: *mut Trie<TK, TV>,
+    key: TK,
+    position: InsertPosition<TK, TV>,
+    marker: PhantomData<&'t mut Trie<TK, TV>>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Entry<'t, TK, TV> {
+    /// Returns a reference to the key of this entry.
+    pub fn key(&self) -> &TK {
+        match *self {
+            Entry::Occupied(ref entry) => entry.key(),
+            Entry::Vacant(ref entry) => entry.key(),
+        }
+    }
+
+    /// Inserts `default` if the entry is vacant, and returns a mutable
+    /// reference to the value.
+    pub fn or_insert(self, default: TV) -> &'t mut TV {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Inserts the result of `default()` if the entry is vacant, and returns a
+    /// mutable reference to the value.
+    pub fn or_insert_with<F: FnOnce() -> TV>(self, default: F) -> &'t mut TV {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Inserts the result of `default(key)` if the entry is vacant, and
+    /// returns a mutable reference to the value.
+    pub fn or_insert_with_key<F: FnOnce(&TK) -> TV>(self, default: F) -> &'t mut TV {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let val = default(entry.key());
+                entry.insert(val)
+            }
+        }
+    }
+
+    /// Calls `f` on the value if the entry is occupied.
+    pub fn and_modify<F: FnOnce(&mut TV)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(entry) => {
+                f(unsafe { &mut (*entry.leaf).val });
+                entry.ancestors.rescore(Trie::score_fn(entry.trie));
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV: Default> Entry<'t, TK, TV> {
+    /// Inserts the default value if the entry is vacant, and returns a
+    /// mutable reference to the value.
+    pub fn or_default(self) -> &'t mut TV {
+        self.or_insert_with(Default::default)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> OccupiedEntry<'t, TK, TV> {
+    pub(crate) fn new(
+        trie: *mut Trie<TK, TV>,
+        leaf: *mut LeafNode<TK, TV>,
+        parent: NodeParent<TK, TV>,
+        ancestors: Ancestors<TK, TV>,
+    ) -> Self {
+        OccupiedEntry {
+            trie,
+            leaf,
+            parent,
+            ancestors,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the key of this entry.
+    pub fn key(&self) -> &TK {
+        unsafe { &(*self.leaf).key }
+    }
+
+    /// Returns a reference to the value of this entry.
+    pub fn get(&self) -> &TV {
+        unsafe { &(*self.leaf).val }
+    }
+
+    /// Returns a mutable reference to the value of this entry.
+    pub fn get_mut(&mut self) -> &mut TV {
+        Trie::mark_scores_stale(self.trie);
+        unsafe { &mut (*self.leaf).val }
+    }
+
+    /// Converts the entry into a mutable reference to its value, bound to the
+    /// lifetime of the trie.
+    pub fn into_mut(self) -> &'t mut TV {
+        Trie::mark_scores_stale(self.trie);
+        unsafe { &mut (*self.leaf).val }
+    }
+
+    /// Replaces the value of this entry, and returns the previous one.
+    pub fn insert(&mut self, val: TV) -> TV {
+        let previous = unsafe { ::std::mem::replace(&mut (*self.leaf).val, val) };
+        self.ancestors.rescore(Trie::score_fn(self.trie));
+        previous
+    }
+
+    /// Removes the entry from the trie, and returns its value.
+    pub fn remove(self) -> TV {
+        self.remove_entry().1
+    }
+
+    /// Removes the entry from the trie, and returns its key and its value.
+    pub fn remove_entry(self) -> (TK, TV) {
+        let leaf = Trie::unlink_leaf(self.trie, self.parent, self.ancestors);
+        (leaf.key, leaf.val)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> VacantEntry<'t, TK, TV> {
+    pub(crate) fn new(trie: *mut Trie<TK, TV>, key: TK, position: InsertPosition<TK, TV>) -> Self {
+        VacantEntry {
+            trie,
+            key,
+            position,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the key that would be used when inserting a
+    /// value through this entry.
+    pub fn key(&self) -> &TK {
+        &self.key
+    }
+
+    /// Takes ownership of the key.
+    pub fn into_key(self) -> TK {
+        self.key
+    }
+
+    /// Inserts `val` with the key of this entry, and returns a mutable
+    /// reference to it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the insertion would make the trie higher than its
+    /// `max_height`.
+    pub fn insert(self, val: TV) -> &'t mut TV {
+        match self.try_insert(val) {
+            Ok(val) => val,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Inserts `val` with the key of this entry, and returns a mutable
+    /// reference to it, or an error holding the key and the value if the
+    /// insertion was refused.
+    pub fn try_insert(self, val: TV) -> Result<&'t mut TV, InsertError<TK, TV>> {
+        if let InsertPosition::TooHigh = self.position {
+            return Err(InsertError {
+                key: self.key,
+                val,
+                kind: InsertErrorKind::MaxHeight,
+            });
+        }
+        let leaf = Trie::insert_at(self.trie, self.position, self.key, val);
+        Trie::mark_scores_stale(self.trie);
+        Ok(unsafe { &mut (*leaf).val })
+    }
+}
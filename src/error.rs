@@ -0,0 +1,49 @@
+use std::error::Error;
+use std::fmt;
+
+/// The reason why an insertion was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertErrorKind {
+    /// The new key would have made the trie higher than its `max_height`.
+    MaxHeight,
+    /// The new key does not sort at the position of the cursor it was
+    /// inserted with.
+    Unordered,
+}
+
+/// An error returned when a `(key, value)` pair cannot be inserted.
+///
+/// The rejected key and value are handed back to the caller.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InsertError<TK, TV> {
+    /// The key that was not inserted.
+    pub key: TK,
+    /// The value that was not inserted.
+    pub val: TV,
+    /// Why the insertion was refused.
+    pub kind: InsertErrorKind,
+}
+
+impl<TK, TV> InsertError<TK, TV> {
+    /// Returns the rejected key and value.
+    pub fn into_inner(self) -> (TK, TV) {
+        (self.key, self.val)
+    }
+}
+
+impl fmt::Display for InsertErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InsertErrorKind::MaxHeight => write!(f, "maximum trie height exceeded"),
+            InsertErrorKind::Unordered => write!(f, "key out of order at the cursor position"),
+        }
+    }
+}
+
+impl<TK, TV> fmt::Display for InsertError<TK, TV> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "insertion refused: {}", self.kind)
+    }
+}
+
+impl<TK: fmt::Debug, TV: fmt::Debug> Error for InsertError<TK, TV> {}
@@ -0,0 +1,167 @@
+use super::automaton::Automaton;
+
+/// A token of a glob pattern.
+#[derive(Clone, Debug)]
+enum Token {
+    /// A literal byte.
+    Byte(u8),
+    /// `?`: any byte.
+    AnyByte,
+    /// `*`: any sequence of bytes, possibly empty.
+    AnyBytes,
+    /// `[...]`: a byte within one of the inclusive ranges, or outside all of
+    /// them if the class is negated.
+    Class {
+        negated: bool,
+        ranges: Vec<(u8, u8)>,
+    },
+}
+
+impl Token {
+    fn matches(&self, byte: u8) -> bool {
+        match *self {
+            Token::Byte(expected) => byte == expected,
+            Token::AnyByte | Token::AnyBytes => true,
+            Token::Class {
+                negated,
+                ref ranges,
+            } => ranges.iter().any(|&(lo, hi)| lo <= byte && byte <= hi) != negated,
+        }
+    }
+}
+
+/// A compiled glob pattern, matched as a non-deterministic automaton whose
+/// states are positions in the pattern.
+///
+/// `?` matches any byte, `*` any sequence of bytes, `[abc]`, `[a-z]` and
+/// their negations `[!...]` or `[^...]` a byte from a class, and `\` escapes
+/// the next byte. A `[` without a matching `]` is a literal byte.
+#[derive(Clone, Debug)]
+pub(crate) struct Glob {
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    pub fn new(pattern: &[u8]) -> Self {
+        let mut tokens = vec![];
+        let mut i = 0;
+        while i < pattern.len() {
+            let token = match pattern[i] {
+                b'?' => Token::AnyByte,
+                b'*' => Token::AnyBytes,
+                b'\\' if i + 1 < pattern.len() => {
+                    i += 1;
+                    Token::Byte(pattern[i])
+                }
+                b'[' => match Self::parse_class(&pattern[i + 1..]) {
+                    Some((token, len)) => {
+                        i += len;
+                        token
+                    }
+                    None => Token::Byte(b'['),
+                },
+                byte => Token::Byte(byte),
+            };
+            tokens.push(token);
+            i += 1;
+        }
+        Glob { tokens }
+    }
+
+    /// Parses a character class following a `[`, and returns it along with
+    /// the number of bytes it spans, including the closing `]`.
+    fn parse_class(class: &[u8]) -> Option<(Token, usize)> {
+        let negated = matches!(class.first(), Some(&b'!') | Some(&b'^'));
+        let mut i = negated as usize;
+        let mut ranges = vec![];
+        loop {
+            let lo = *class.get(i)?;
+            // A `]` right after the opening bracket is a member of the class.
+            if lo == b']' && i > negated as usize {
+                return Some((Token::Class { negated, ranges }, i + 1));
+            }
+            match (class.get(i + 1), class.get(i + 2)) {
+                (Some(&b'-'), Some(&hi)) if hi != b']' => {
+                    ranges.push((lo, hi));
+                    i += 3;
+                }
+                _ => {
+                    ranges.push((lo, lo));
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    /// Adds the states reachable by skipping `*` tokens, and sorts the
+    /// states.
+    fn close(&self, mut states: Vec<usize>) -> Vec<usize> {
+        let mut i = 0;
+        while i < states.len() {
+            let state = states[i];
+            if let Some(&Token::AnyBytes) = self.tokens.get(state) {
+                if !states.contains(&(state + 1)) {
+                    states.push(state + 1);
+                }
+            }
+            i += 1;
+        }
+        states.sort_unstable();
+        states
+    }
+}
+
+impl Automaton for Glob {
+    type State = Vec<usize>;
+
+    fn start(&self) -> Self::State {
+        self.close(vec![0])
+    }
+
+    /// Returns the states reached from `states` by reading `byte`. No states
+    /// are returned if the pattern cannot match anymore.
+    fn transition(&self, states: &Self::State, byte: u8) -> Self::State {
+        let mut next = vec![];
+        for &state in states {
+            let token = match self.tokens.get(state) {
+                None => continue,
+                Some(token) => token,
+            };
+            if !token.matches(byte) {
+                continue;
+            }
+            let target = match *token {
+                Token::AnyBytes => state,
+                _ => state + 1,
+            };
+            if !next.contains(&target) {
+                next.push(target);
+            }
+        }
+        self.close(next)
+    }
+
+    #[inline]
+    fn is_match(&self, states: &Self::State) -> bool {
+        states.last() == Some(&self.tokens.len())
+    }
+
+    #[inline]
+    fn can_match(&self, states: &Self::State) -> bool {
+        !states.is_empty()
+    }
+
+    /// Returns the literal bytes expected next, unless a wildcard or a class
+    /// can read other bytes.
+    fn next_bytes(&self, states: &Self::State) -> Option<Vec<u8>> {
+        let mut bytes = vec![];
+        for &state in states {
+            match self.tokens.get(state) {
+                None => {}
+                Some(&Token::Byte(byte)) => bytes.push(byte),
+                Some(_) => return None,
+            }
+        }
+        Some(bytes)
+    }
+}
@@ -1,26 +1,35 @@
-use super::node::Node;
+use std::collections::VecDeque;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+
+use super::automaton::{Automaton, Levenshtein};
+use super::glob::Glob;
+use super::node::{LeafNode, Node};
+use super::trie::{ExtractFrame, SubtreeRelation};
 use super::Trie;
 
 /// An iterator over keys matching a prefix.
 #[derive(Clone, Debug)]
 pub struct TriePrefixIterator<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
-    trie: &'t Trie<TK, TV>,
-    prefix: &'t TK,
-    todo: Vec<&'t Node<TK, TV>>,
+    leaves: Leaves<'t, TK, TV>,
+    prefix_len: usize,
     include_prefix: bool,
+    prefix_pending: bool,
 }
 
 impl<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> TriePrefixIterator<'t, TK, TV> {
-    pub fn new(trie: &'t Trie<TK, TV>, key: &'t TK, include_prefix: bool) -> Self {
-        let todo = match trie.root() {
-            None => vec![],
-            Some(root) => vec![root],
-        };
+    pub fn new<Q: ?Sized + AsRef<[u8]>>(
+        trie: &'t Trie<TK, TV>,
+        prefix: &Q,
+        include_prefix: bool,
+    ) -> Self {
+        let prefix = prefix.as_ref();
+        let root = trie.prefix_root(prefix);
         TriePrefixIterator {
-            trie,
-            prefix: key,
-            todo,
+            leaves: Leaves::new(root.into_iter().collect(), leaf_count(root, trie.len())),
+            prefix_len: prefix.len(),
             include_prefix,
+            prefix_pending: root.is_some_and(|t| t.first_leaf().key.as_ref().len() == prefix.len()),
         }
     }
 }
@@ -32,21 +41,1154 @@ impl<'t, TK: PartialEq + AsRef<[u8]>, TV> TriePrefixIterator<'t, TK, TV> {
         self.include_prefix = true;
         self
     }
+
+    #[inline]
+    fn is_wanted(&mut self, leaf: &LeafNode<TK, TV>) -> bool {
+        if leaf.key.as_ref().len() != self.prefix_len {
+            return true;
+        }
+        self.prefix_pending = false;
+        self.include_prefix
+    }
 }
 
 impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for TriePrefixIterator<'t, TK, TV> {
     type Item = (&'t TK, &'t TV);
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self
-            .trie
-            .prefix_find_next(self.prefix, &mut self.todo, self.include_prefix)
-        {
-            None => None,
-            Some(leaf) => {
-                self.include_prefix = false;
-                Some((&leaf.key, &leaf.val))
+        while let Some(leaf) = self.leaves.next() {
+            if self.is_wanted(leaf) {
+                return Some((&leaf.key, &leaf.val));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let skipped = (self.prefix_pending && !self.include_prefix) as usize;
+        remaining_hint(self.leaves.remaining - skipped)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator for TriePrefixIterator<'t, TK, TV> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while let Some(leaf) = self.leaves.next_back() {
+            if self.is_wanted(leaf) {
+                return Some((&leaf.key, &leaf.val));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(feature = "subtree-counts")]
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> ExactSizeIterator for TriePrefixIterator<'t, TK, TV> {}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for TriePrefixIterator<'t, TK, TV> {}
+
+/// A mutable iterator over keys matching a prefix.
+#[derive(Debug)]
+pub struct TriePrefixIteratorMut<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    leaves: LeavesMut<'t, TK, TV>,
+    prefix_len: usize,
+    include_prefix: bool,
+    prefix_pending: bool,
+}
+
+impl<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> TriePrefixIteratorMut<'t, TK, TV> {
+    pub fn new<Q: ?Sized + AsRef<[u8]>>(
+        trie: &'t mut Trie<TK, TV>,
+        prefix: &Q,
+        include_prefix: bool,
+    ) -> Self {
+        let prefix = prefix.as_ref();
+        let len = trie.len();
+        let root = trie.prefix_root_mut(prefix);
+        let remaining = leaf_count(root.as_deref(), len);
+        let prefix_pending = root
+            .as_deref()
+            .is_some_and(|t| t.first_leaf().key.as_ref().len() == prefix.len());
+        TriePrefixIteratorMut {
+            leaves: LeavesMut::new(root.into_iter().collect(), remaining),
+            prefix_len: prefix.len(),
+            include_prefix,
+            prefix_pending,
+        }
+    }
+
+    /// If a key equal to the prefix itself is found, include it in the results.
+    #[inline]
+    pub fn include_prefix(mut self) -> Self {
+        self.include_prefix = true;
+        self
+    }
+
+    #[inline]
+    fn is_wanted(&mut self, key: &TK) -> bool {
+        if key.as_ref().len() != self.prefix_len {
+            return true;
+        }
+        self.prefix_pending = false;
+        self.include_prefix
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for TriePrefixIteratorMut<'t, TK, TV> {
+    type Item = (&'t TK, &'t mut TV);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((key, val)) = self.leaves.next() {
+            if self.is_wanted(key) {
+                return Some((key, val));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let skipped = (self.prefix_pending && !self.include_prefix) as usize;
+        remaining_hint(self.leaves.remaining - skipped)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator
+    for TriePrefixIteratorMut<'t, TK, TV>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while let Some((key, val)) = self.leaves.next_back() {
+            if self.is_wanted(key) {
+                return Some((key, val));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(feature = "subtree-counts")]
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> ExactSizeIterator for TriePrefixIteratorMut<'t, TK, TV> {}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for TriePrefixIteratorMut<'t, TK, TV> {}
+
+/// An iterator over the keys that are prefixes of a given key, shortest first.
+#[derive(Clone, Debug)]
+pub struct PrefixesOf<'t, 'k, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    node: Option<&'t Node<TK, TV>>,
+    key: &'k [u8],
+}
+
+impl<'t, 'k, TK: PartialEq + AsRef<[u8]>, TV> PrefixesOf<'t, 'k, TK, TV> {
+    pub(crate) fn new(root: Option<&'t Node<TK, TV>>, key: &'k [u8]) -> Self {
+        PrefixesOf { node: root, key }
+    }
+}
+
+impl<'t, 'k, TK: PartialEq + AsRef<[u8]>, TV> Iterator for PrefixesOf<'t, 'k, TK, TV> {
+    type Item = (&'t TK, &'t TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        Trie::next_prefix_of(&mut self.node, self.key).map(|leaf| (&leaf.key, &leaf.val))
+    }
+}
+
+impl<'t, 'k, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for PrefixesOf<'t, 'k, TK, TV> {}
+
+/// An iterator over the entries whose key is accepted by an automaton, in
+/// lexicographic order of the keys.
+///
+/// Each pending subtree carries the state of the automaton after the bytes
+/// shared by all its keys, and is skipped as soon as the automaton cannot
+/// match anymore. Where the automaton only accepts some bytes next, only the
+/// children holding these bytes are visited.
+#[derive(Clone, Debug)]
+pub struct Search<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't, A: Automaton> {
+    todo: Vec<(&'t Node<TK, TV>, usize, A::State)>,
+    automaton: A,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV, A: Automaton> Search<'t, TK, TV, A> {
+    pub(crate) fn new(trie: &'t Trie<TK, TV>, automaton: A) -> Self {
+        let state = automaton.start();
+        let todo = match trie.root() {
+            Some(root) if automaton.can_match(&state) => vec![(root, 0, state)],
+            _ => vec![],
+        };
+        Search { todo, automaton }
+    }
+
+    /// Reads `bytes`, and returns `false` if the automaton cannot match
+    /// anymore.
+    fn feed(&self, state: &mut A::State, bytes: &[u8]) -> bool {
+        for &byte in bytes {
+            *state = self.automaton.transition(state, byte);
+            if !self.automaton.can_match(state) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Returns the nibbles at `index` of the keys that the automaton may
+    /// accept, given that these keys share `key` before that index, in
+    /// descending order, or `None` if any nibble may be accepted.
+    fn next_nibbles(&self, state: &A::State, key: &[u8], index: usize) -> Option<Vec<usize>> {
+        let bytes = self.automaton.next_bytes(state)?;
+        let mut nibbles: Vec<usize> = if index & 1 == 0 {
+            bytes.iter().map(|&byte| 1 + (byte >> 4) as usize).collect()
+        } else {
+            let high = key[index / 2] >> 4;
+            bytes
+                .iter()
+                .filter(|&&byte| byte >> 4 == high)
+                .map(|&byte| 1 + (byte & 0xf) as usize)
+                .collect()
+        };
+        if index & 1 == 0 && self.automaton.is_match(state) {
+            nibbles.push(0);
+        }
+        nibbles.sort_unstable_by(|a, b| b.cmp(a));
+        nibbles.dedup();
+        Some(nibbles)
+    }
+
+    /// Returns the next accepted leaf, along with the final state of the
+    /// automaton.
+    fn next_match(&mut self) -> Option<(&'t LeafNode<TK, TV>, A::State)> {
+        while let Some((t, consumed, mut state)) = self.todo.pop() {
+            match *t {
+                Node::Leaf(ref leaf) => {
+                    let key = &leaf.key.as_ref()[consumed..];
+                    if self.feed(&mut state, key) && self.automaton.is_match(&state) {
+                        return Some((leaf, state));
+                    }
+                }
+                Node::Internal(ref internal) => {
+                    let shared = internal.index / 2;
+                    let key = t.first_leaf().key.as_ref();
+                    if !self.feed(&mut state, &key[consumed..shared]) {
+                        continue;
+                    }
+                    match self.next_nibbles(&state, key, internal.index) {
+                        None => {
+                            for node in internal.nibbles.all().iter().rev() {
+                                self.todo.push((node, shared, state.clone()));
+                            }
+                        }
+                        Some(nibbles) => {
+                            for nibble in nibbles {
+                                if let Some(node) = internal.nibbles.get(nibble) {
+                                    self.todo.push((node, shared, state.clone()));
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => unsafe { debug_unreachable!() },
+            }
+        }
+        None
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV, A: Automaton> Iterator for Search<'t, TK, TV, A> {
+    type Item = (&'t TK, &'t TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_match().map(|(leaf, _)| (&leaf.key, &leaf.val))
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV, A: Automaton> FusedIterator for Search<'t, TK, TV, A> {}
+
+/// An iterator over the entries whose key is within an edit distance of a
+/// query, in lexicographic order of the keys.
+#[derive(Clone, Debug)]
+pub struct FuzzyIter<'t, 'q, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    search: Search<'t, TK, TV, Levenshtein<'q>>,
+}
+
+impl<'t, 'q, TK: PartialEq + AsRef<[u8]>, TV> FuzzyIter<'t, 'q, TK, TV> {
+    pub(crate) fn new(trie: &'t Trie<TK, TV>, query: &'q [u8], max_distance: usize) -> Self {
+        FuzzyIter {
+            search: Search::new(trie, Levenshtein::new(query, max_distance)),
+        }
+    }
+}
+
+impl<'t, 'q, TK: PartialEq + AsRef<[u8]>, TV> Iterator for FuzzyIter<'t, 'q, TK, TV> {
+    type Item = (&'t TK, &'t TV, usize);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let (leaf, row) = self.search.next_match()?;
+        let distance = self.search.automaton.distance(&row);
+        Some((&leaf.key, &leaf.val, distance))
+    }
+}
+
+impl<'t, 'q, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for FuzzyIter<'t, 'q, TK, TV> {}
+
+/// An iterator over the entries whose key matches a glob pattern, in
+/// lexicographic order of the keys.
+#[derive(Clone, Debug)]
+pub struct GlobIter<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    search: Search<'t, TK, TV, Glob>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> GlobIter<'t, TK, TV> {
+    pub(crate) fn new(trie: &'t Trie<TK, TV>, pattern: &[u8]) -> Self {
+        GlobIter {
+            search: Search::new(trie, Glob::new(pattern)),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for GlobIter<'t, TK, TV> {
+    type Item = (&'t TK, &'t TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.search.next()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for GlobIter<'t, TK, TV> {}
+
+/// Returns the number of leaves under `nodes` with the `subtree-counts`
+/// feature, or `len`, the number of keys of the whole trie, as an upper bound
+/// without it.
+#[cfg(feature = "subtree-counts")]
+fn leaf_count<'a, TK: 'a + PartialEq + AsRef<[u8]>, TV: 'a>(
+    nodes: impl IntoIterator<Item = &'a Node<TK, TV>>,
+    _len: usize,
+) -> usize {
+    nodes.into_iter().map(Node::count).sum()
+}
+
+/// Returns the number of leaves under `nodes` with the `subtree-counts`
+/// feature, or `len`, the number of keys of the whole trie, as an upper bound
+/// without it.
+#[cfg(not(feature = "subtree-counts"))]
+#[inline]
+fn leaf_count<'a, TK: 'a + PartialEq + AsRef<[u8]>, TV: 'a>(
+    _nodes: impl IntoIterator<Item = &'a Node<TK, TV>>,
+    len: usize,
+) -> usize {
+    len
+}
+
+/// Returns the size hint of an iterator over subtrees with at most
+/// `remaining` leaves left, which is exact with the `subtree-counts` feature.
+#[inline]
+fn remaining_hint(remaining: usize) -> (usize, Option<usize>) {
+    if cfg!(feature = "subtree-counts") {
+        (remaining, Some(remaining))
+    } else {
+        (0, Some(remaining))
+    }
+}
+
+/// A depth-first walk over the leaves of a subtree, in lexicographic order.
+///
+/// The walk can proceed from both ends: children are expanded in ascending
+/// nibble order at the front, and in descending nibble order at the back.
+/// `remaining` is an upper bound on the number of leaves left, which is exact
+/// when walking the whole trie, or any subtree with the `subtree-counts`
+/// feature.
+#[derive(Clone, Debug)]
+struct Leaves<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    todo: VecDeque<&'t Node<TK, TV>>,
+    remaining: usize,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Leaves<'t, TK, TV> {
+    fn new(todo: VecDeque<&'t Node<TK, TV>>, remaining: usize) -> Self {
+        let remaining = if todo.is_empty() { 0 } else { remaining };
+        Leaves { todo, remaining }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for Leaves<'t, TK, TV> {
+    type Item = &'t LeafNode<TK, TV>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(t) = self.todo.pop_front() {
+            match *t {
+                Node::Leaf(ref leaf) => {
+                    self.remaining -= 1;
+                    return Some(leaf);
+                }
+                Node::Internal(ref internal) => {
+                    for node in internal.nibbles.all().iter().rev() {
+                        self.todo.push_front(node);
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        self.remaining = 0;
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator for Leaves<'t, TK, TV> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while let Some(t) = self.todo.pop_back() {
+            match *t {
+                Node::Leaf(ref leaf) => {
+                    self.remaining -= 1;
+                    return Some(leaf);
+                }
+                Node::Internal(ref internal) => {
+                    self.todo.extend(internal.nibbles.all().iter());
+                }
+                _ => unreachable!(),
+            }
+        }
+        self.remaining = 0;
+        None
+    }
+}
+
+/// A depth-first walk over the leaves of a subtree, in lexicographic order,
+/// with mutable access to the values.
+#[derive(Debug)]
+struct LeavesMut<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    todo: VecDeque<&'t mut Node<TK, TV>>,
+    remaining: usize,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> LeavesMut<'t, TK, TV> {
+    fn new(todo: VecDeque<&'t mut Node<TK, TV>>, remaining: usize) -> Self {
+        let remaining = if todo.is_empty() { 0 } else { remaining };
+        LeavesMut { todo, remaining }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for LeavesMut<'t, TK, TV> {
+    type Item = (&'t TK, &'t mut TV);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(t) = self.todo.pop_front() {
+            match *t {
+                Node::Leaf(ref mut leaf) => {
+                    self.remaining -= 1;
+                    return Some((&leaf.key, &mut leaf.val));
+                }
+                Node::Internal(ref mut internal) => {
+                    for node in internal.nibbles.all_mut().iter_mut().rev() {
+                        self.todo.push_front(node);
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        self.remaining = 0;
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator for LeavesMut<'t, TK, TV> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while let Some(t) = self.todo.pop_back() {
+            match *t {
+                Node::Leaf(ref mut leaf) => {
+                    self.remaining -= 1;
+                    return Some((&leaf.key, &mut leaf.val));
+                }
+                Node::Internal(ref mut internal) => {
+                    self.todo.extend(internal.nibbles.all_mut().iter_mut());
+                }
+                _ => unreachable!(),
+            }
+        }
+        self.remaining = 0;
+        None
+    }
+}
+
+/// An iterator over the entries of a trie within a range of keys, in
+/// lexicographic order.
+#[derive(Clone, Debug)]
+pub struct Range<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    leaves: Leaves<'t, TK, TV>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Range<'t, TK, TV> {
+    pub(crate) fn new(todo: VecDeque<&'t Node<TK, TV>>, len: usize) -> Self {
+        let remaining = leaf_count(todo.iter().copied(), len);
+        Range {
+            leaves: Leaves::new(todo, remaining),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for Range<'t, TK, TV> {
+    type Item = (&'t TK, &'t TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.leaves.next().map(|leaf| (&leaf.key, &leaf.val))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        remaining_hint(self.leaves.remaining)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator for Range<'t, TK, TV> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.leaves.next_back().map(|leaf| (&leaf.key, &leaf.val))
+    }
+}
+
+#[cfg(feature = "subtree-counts")]
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> ExactSizeIterator for Range<'t, TK, TV> {}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for Range<'t, TK, TV> {}
+
+/// A mutable iterator over the entries of a trie within a range of keys, in
+/// lexicographic order.
+#[derive(Debug)]
+pub struct RangeMut<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    leaves: LeavesMut<'t, TK, TV>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> RangeMut<'t, TK, TV> {
+    pub(crate) fn new(todo: VecDeque<&'t mut Node<TK, TV>>, len: usize) -> Self {
+        let remaining = leaf_count(todo.iter().map(|t| &**t), len);
+        RangeMut {
+            leaves: LeavesMut::new(todo, remaining),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for RangeMut<'t, TK, TV> {
+    type Item = (&'t TK, &'t mut TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.leaves.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        remaining_hint(self.leaves.remaining)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator for RangeMut<'t, TK, TV> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.leaves.next_back()
+    }
+}
+
+#[cfg(feature = "subtree-counts")]
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> ExactSizeIterator for RangeMut<'t, TK, TV> {}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for RangeMut<'t, TK, TV> {}
+
+/// An iterator over the entries of a trie, in lexicographic order of the keys.
+#[derive(Clone, Debug)]
+pub struct Iter<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    leaves: Leaves<'t, TK, TV>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iter<'t, TK, TV> {
+    pub(crate) fn new(trie: &'t Trie<TK, TV>) -> Self {
+        Iter {
+            leaves: Leaves::new(trie.root().into_iter().collect(), trie.len()),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for Iter<'t, TK, TV> {
+    type Item = (&'t TK, &'t TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.leaves.next().map(|leaf| (&leaf.key, &leaf.val))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.leaves.size_hint()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator for Iter<'t, TK, TV> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.leaves.next_back().map(|leaf| (&leaf.key, &leaf.val))
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> ExactSizeIterator for Iter<'t, TK, TV> {}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for Iter<'t, TK, TV> {}
+
+/// A mutable iterator over the entries of a trie, in lexicographic order of
+/// the keys.
+#[derive(Debug)]
+pub struct IterMut<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    leaves: LeavesMut<'t, TK, TV>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> IterMut<'t, TK, TV> {
+    pub(crate) fn new(trie: &'t mut Trie<TK, TV>) -> Self {
+        let len = trie.len();
+        IterMut {
+            leaves: LeavesMut::new(trie.root_mut().into_iter().collect(), len),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for IterMut<'t, TK, TV> {
+    type Item = (&'t TK, &'t mut TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.leaves.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.leaves.size_hint()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator for IterMut<'t, TK, TV> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.leaves.next_back()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> ExactSizeIterator for IterMut<'t, TK, TV> {}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for IterMut<'t, TK, TV> {}
+
+/// An iterator over the keys of a trie, in lexicographic order.
+#[derive(Clone, Debug)]
+pub struct Keys<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    leaves: Leaves<'t, TK, TV>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Keys<'t, TK, TV> {
+    pub(crate) fn new(trie: &'t Trie<TK, TV>) -> Self {
+        Keys {
+            leaves: Leaves::new(trie.root().into_iter().collect(), trie.len()),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for Keys<'t, TK, TV> {
+    type Item = &'t TK;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.leaves.next().map(|leaf| &leaf.key)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.leaves.size_hint()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator for Keys<'t, TK, TV> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.leaves.next_back().map(|leaf| &leaf.key)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> ExactSizeIterator for Keys<'t, TK, TV> {}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for Keys<'t, TK, TV> {}
+
+/// An iterator over the values of a trie, in lexicographic order of the keys.
+#[derive(Clone, Debug)]
+pub struct Values<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    leaves: Leaves<'t, TK, TV>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Values<'t, TK, TV> {
+    pub(crate) fn new(trie: &'t Trie<TK, TV>) -> Self {
+        Values {
+            leaves: Leaves::new(trie.root().into_iter().collect(), trie.len()),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for Values<'t, TK, TV> {
+    type Item = &'t TV;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.leaves.next().map(|leaf| &leaf.val)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.leaves.size_hint()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator for Values<'t, TK, TV> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.leaves.next_back().map(|leaf| &leaf.val)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> ExactSizeIterator for Values<'t, TK, TV> {}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for Values<'t, TK, TV> {}
+
+/// A mutable iterator over the values of a trie, in lexicographic order of
+/// the keys.
+#[derive(Debug)]
+pub struct ValuesMut<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    leaves: LeavesMut<'t, TK, TV>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> ValuesMut<'t, TK, TV> {
+    pub(crate) fn new(trie: &'t mut Trie<TK, TV>) -> Self {
+        let len = trie.len();
+        ValuesMut {
+            leaves: LeavesMut::new(trie.root_mut().into_iter().collect(), len),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for ValuesMut<'t, TK, TV> {
+    type Item = &'t mut TV;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.leaves.next().map(|(_, val)| val)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.leaves.size_hint()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator for ValuesMut<'t, TK, TV> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.leaves.next_back().map(|(_, val)| val)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> ExactSizeIterator for ValuesMut<'t, TK, TV> {}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for ValuesMut<'t, TK, TV> {}
+
+/// An owning iterator over the entries of a trie, in lexicographic order of
+/// the keys.
+#[derive(Debug)]
+pub struct IntoIter<TK: PartialEq + AsRef<[u8]>, TV> {
+    todo: VecDeque<Node<TK, TV>>,
+    remaining: usize,
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> IntoIter<TK, TV> {
+    pub(crate) fn new(root: Option<Node<TK, TV>>, len: usize) -> Self {
+        IntoIter {
+            todo: root.into_iter().collect(),
+            remaining: len,
+        }
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> Iterator for IntoIter<TK, TV> {
+    type Item = (TK, TV);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(t) = self.todo.pop_front() {
+            match t {
+                Node::Leaf(leaf) => {
+                    self.remaining -= 1;
+                    return Some((leaf.key, leaf.val));
+                }
+                Node::Internal(internal) => {
+                    for node in internal.nibbles.into_vec().into_iter().rev() {
+                        self.todo.push_front(node);
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator for IntoIter<TK, TV> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while let Some(t) = self.todo.pop_back() {
+            match t {
+                Node::Leaf(leaf) => {
+                    self.remaining -= 1;
+                    return Some((leaf.key, leaf.val));
+                }
+                Node::Internal(internal) => {
+                    self.todo.extend(internal.nibbles.into_vec());
+                }
+                _ => unreachable!(),
+            }
+        }
+        None
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> ExactSizeIterator for IntoIter<TK, TV> {}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for IntoIter<TK, TV> {}
+
+/// A draining iterator over the entries of a trie, in lexicographic order of
+/// the keys.
+///
+/// The trie is left empty, even if the iterator is not fully consumed.
+#[derive(Debug)]
+pub struct Drain<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    inner: IntoIter<TK, TV>,
+    marker: PhantomData<&'t mut Trie<TK, TV>>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Drain<'t, TK, TV> {
+    pub(crate) fn new(inner: IntoIter<TK, TV>) -> Self {
+        Drain {
+            inner,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for Drain<'t, TK, TV> {
+    type Item = (TK, TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> DoubleEndedIterator for Drain<'t, TK, TV> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> ExactSizeIterator for Drain<'t, TK, TV> {}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for Drain<'t, TK, TV> {}
+
+/// An iterator removing the entries of a trie matching a predicate, in
+/// lexicographic order of the keys.
+pub struct ExtractIf<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't, F> {
+    trie: *mut Trie<TK, TV>,
+    frames: Vec<ExtractFrame<TK, TV>>,
+    started: bool,
+    pred: F,
+    /// Whether the scores of the trie were stale before `pred` was handed
+    /// the values.
+    scores_stale: bool,
+    marker: PhantomData<&'t mut Trie<TK, TV>>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV, F: FnMut(&TK, &mut TV) -> bool> ExtractIf<'t, TK, TV, F> {
+    pub(crate) fn new(trie: &'t mut Trie<TK, TV>, pred: F) -> Self {
+        let trie: *mut Trie<TK, TV> = trie;
+        ExtractIf {
+            trie,
+            frames: vec![],
+            started: false,
+            pred,
+            scores_stale: Trie::mark_scores_stale(trie),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV, F> Drop for ExtractIf<'t, TK, TV, F> {
+    fn drop(&mut self) {
+        Trie::end_extract(self.trie, &self.frames, self.scores_stale);
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV, F: FnMut(&TK, &mut TV) -> bool> Iterator
+    for ExtractIf<'t, TK, TV, F>
+{
+    type Item = (TK, TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        Trie::extract_next(
+            self.trie,
+            &mut self.frames,
+            &mut self.started,
+            &mut self.pred,
+        )
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(unsafe { (*self.trie).len() }))
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV, F: FnMut(&TK, &mut TV) -> bool> FusedIterator
+    for ExtractIf<'t, TK, TV, F>
+{
+}
+
+/// A walk over the leaves of two tries in lockstep, in lexicographic order.
+///
+/// Subtrees are only expanded as long as they may overlap a subtree of the
+/// other trie; subtrees whose keys are not wanted are skipped as a whole.
+#[derive(Clone, Debug)]
+struct Lockstep<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    a: Vec<&'t Node<TK, TV>>,
+    b: Vec<&'t Node<TK, TV>>,
+    /// Yield the keys only present in the first trie.
+    only_a: bool,
+    /// Yield the keys only present in the second trie.
+    only_b: bool,
+    /// Yield the keys present in both tries, from the first trie.
+    both: bool,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Lockstep<'t, TK, TV> {
+    fn new(
+        a: &'t Trie<TK, TV>,
+        b: &'t Trie<TK, TV>,
+        only_a: bool,
+        only_b: bool,
+        both: bool,
+    ) -> Self {
+        Lockstep {
+            a: a.root().into_iter().collect(),
+            b: b.root().into_iter().collect(),
+            only_a,
+            only_b,
+            both,
+        }
+    }
+
+    /// Pushes the children of `t` to `stack`, smallest on top. If `other` is
+    /// an internal node branching at the same index, children with no
+    /// counterpart in `other` are skipped.
+    fn expand(
+        stack: &mut Vec<&'t Node<TK, TV>>,
+        t: &'t Node<TK, TV>,
+        other: Option<&'t Node<TK, TV>>,
+    ) {
+        for (nibble, node) in t.as_internal().nibbles.entries().rev() {
+            match other {
+                Some(other) if !other.as_internal().nibbles.has_sparse_index(nibble) => {}
+                _ => stack.push(node),
+            }
+        }
+    }
+
+    /// Takes the subtree on top of `stack`, whose keys are not in the other
+    /// trie, and returns its leaf if it is one and `wanted` is `true`.
+    fn take(stack: &mut Vec<&'t Node<TK, TV>>, wanted: bool) -> Option<&'t LeafNode<TK, TV>> {
+        let t = stack.pop()?;
+        if !wanted {
+            return None;
+        }
+        match *t {
+            Node::Leaf(ref leaf) => Some(leaf),
+            _ => {
+                Self::expand(stack, t, None);
+                None
+            }
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for Lockstep<'t, TK, TV> {
+    type Item = &'t LeafNode<TK, TV>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let from_a = match (self.a.last(), self.b.last()) {
+                (None, None) => return None,
+                (Some(_), None) if !self.only_a => return None,
+                (None, Some(_)) if !self.only_b => return None,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(&a), Some(&b)) => match Trie::relate_subtrees(a, b) {
+                    SubtreeRelation::Before => true,
+                    SubtreeRelation::After => false,
+                    SubtreeRelation::Same => {
+                        self.a.pop();
+                        self.b.pop();
+                        if self.both {
+                            return Some(a.as_leaf());
+                        }
+                        continue;
+                    }
+                    SubtreeRelation::Contains => {
+                        self.a.pop();
+                        Self::expand(&mut self.a, a, None);
+                        continue;
+                    }
+                    SubtreeRelation::ContainedIn => {
+                        self.b.pop();
+                        Self::expand(&mut self.b, b, None);
+                        continue;
+                    }
+                    SubtreeRelation::Aligned => {
+                        self.a.pop();
+                        self.b.pop();
+                        Self::expand(&mut self.a, a, if self.only_a { None } else { Some(b) });
+                        Self::expand(&mut self.b, b, if self.only_b { None } else { Some(a) });
+                        continue;
+                    }
+                },
+            };
+            let leaf = if from_a {
+                Self::take(&mut self.a, self.only_a)
+            } else {
+                Self::take(&mut self.b, self.only_b)
+            };
+            if leaf.is_some() {
+                return leaf;
             }
         }
     }
 }
+
+/// An iterator over the entries whose key is in either of two tries, in
+/// lexicographic order.
+#[derive(Clone, Debug)]
+pub struct Union<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    walk: Lockstep<'t, TK, TV>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Union<'t, TK, TV> {
+    pub(crate) fn new(a: &'t Trie<TK, TV>, b: &'t Trie<TK, TV>) -> Self {
+        Union {
+            walk: Lockstep::new(a, b, true, true, true),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for Union<'t, TK, TV> {
+    type Item = (&'t TK, &'t TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walk.next().map(|leaf| (&leaf.key, &leaf.val))
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for Union<'t, TK, TV> {}
+
+/// An iterator over the entries of a trie whose key is also in another trie,
+/// in lexicographic order.
+#[derive(Clone, Debug)]
+pub struct Intersection<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    walk: Lockstep<'t, TK, TV>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Intersection<'t, TK, TV> {
+    pub(crate) fn new(a: &'t Trie<TK, TV>, b: &'t Trie<TK, TV>) -> Self {
+        Intersection {
+            walk: Lockstep::new(a, b, false, false, true),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for Intersection<'t, TK, TV> {
+    type Item = (&'t TK, &'t TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walk.next().map(|leaf| (&leaf.key, &leaf.val))
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for Intersection<'t, TK, TV> {}
+
+/// An iterator over the entries of a trie whose key is not in another trie,
+/// in lexicographic order.
+#[derive(Clone, Debug)]
+pub struct Difference<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    walk: Lockstep<'t, TK, TV>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Difference<'t, TK, TV> {
+    pub(crate) fn new(a: &'t Trie<TK, TV>, b: &'t Trie<TK, TV>) -> Self {
+        Difference {
+            walk: Lockstep::new(a, b, true, false, false),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for Difference<'t, TK, TV> {
+    type Item = (&'t TK, &'t TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walk.next().map(|leaf| (&leaf.key, &leaf.val))
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for Difference<'t, TK, TV> {}
+
+/// An iterator over the entries whose key is in exactly one of two tries, in
+/// lexicographic order.
+#[derive(Clone, Debug)]
+pub struct SymmetricDifference<'t, TK: 't + PartialEq + AsRef<[u8]>, TV: 't> {
+    walk: Lockstep<'t, TK, TV>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> SymmetricDifference<'t, TK, TV> {
+    pub(crate) fn new(a: &'t Trie<TK, TV>, b: &'t Trie<TK, TV>) -> Self {
+        SymmetricDifference {
+            walk: Lockstep::new(a, b, true, true, false),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> Iterator for SymmetricDifference<'t, TK, TV> {
+    type Item = (&'t TK, &'t TV);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walk.next().map(|leaf| (&leaf.key, &leaf.val))
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> FusedIterator for SymmetricDifference<'t, TK, TV> {}
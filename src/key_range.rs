@@ -0,0 +1,65 @@
+use std::ops::{
+    Bound, Range, RangeBounds, RangeFrom, RangeFull, RangeInclusive, RangeTo, RangeToInclusive,
+};
+
+/// A range of keys, for `Trie::range()`.
+///
+/// This is implemented for all the standard range types, with bounds of any
+/// type exposing their bytes. Unlike `RangeBounds<[u8]>`, it accepts `a..b`
+/// over byte slices, and unlike `RangeBounds<Q>`, the type of the bounds
+/// doesn't have to be spelled out for a full range (`..`).
+pub trait KeyRange {
+    /// Returns the lower bound of the range.
+    fn start_bound(&self) -> Bound<&[u8]>;
+
+    /// Returns the upper bound of the range.
+    fn end_bound(&self) -> Bound<&[u8]>;
+}
+
+#[inline]
+fn as_bytes<Q: AsRef<[u8]>>(bound: Bound<&Q>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(bound) => Bound::Included(bound.as_ref()),
+        Bound::Excluded(bound) => Bound::Excluded(bound.as_ref()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+impl KeyRange for RangeFull {
+    #[inline]
+    fn start_bound(&self) -> Bound<&[u8]> {
+        Bound::Unbounded
+    }
+
+    #[inline]
+    fn end_bound(&self) -> Bound<&[u8]> {
+        Bound::Unbounded
+    }
+}
+
+macro_rules! impl_key_range {
+    ($($range:ty),*) => {
+        $(
+            impl<Q: AsRef<[u8]>> KeyRange for $range {
+                #[inline]
+                fn start_bound(&self) -> Bound<&[u8]> {
+                    as_bytes(RangeBounds::start_bound(self))
+                }
+
+                #[inline]
+                fn end_bound(&self) -> Bound<&[u8]> {
+                    as_bytes(RangeBounds::end_bound(self))
+                }
+            }
+        )*
+    };
+}
+
+impl_key_range!(
+    Range<Q>,
+    RangeFrom<Q>,
+    RangeTo<Q>,
+    RangeInclusive<Q>,
+    RangeToInclusive<Q>,
+    (Bound<Q>, Bound<Q>)
+);
@@ -3,12 +3,32 @@
 #[macro_use]
 extern crate debug_unreachable;
 
+mod automaton;
+mod cursor;
+mod entry;
+mod error;
+mod glob;
 mod iterator;
+mod key_range;
 mod node;
+mod set;
 mod sparse_array;
 #[cfg(test)]
 mod test;
 mod trie;
 
-pub use self::iterator::TriePrefixIterator;
+pub use self::automaton::Automaton;
+pub use self::cursor::{Cursor, CursorMut};
+pub use self::entry::{Entry, OccupiedEntry, VacantEntry};
+pub use self::error::{InsertError, InsertErrorKind};
+pub use self::iterator::{
+    Difference, Drain, ExtractIf, FuzzyIter, GlobIter, Intersection, IntoIter, Iter, IterMut, Keys,
+    PrefixesOf, Range, RangeMut, Search, SymmetricDifference, TriePrefixIterator,
+    TriePrefixIteratorMut, Union, Values, ValuesMut,
+};
+pub use self::key_range::KeyRange;
+pub use self::set::{
+    SetDifference, SetIntersection, SetIntoIter, SetIter, SetPrefixIter, SetRange,
+    SetSymmetricDifference, SetUnion, TrieSet,
+};
 pub use self::trie::Trie;
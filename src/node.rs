@@ -1,9 +1,203 @@
+#[cfg(not(any(feature = "subtree-counts", feature = "subtree-scores")))]
+use std::marker::PhantomData;
+
 use super::sparse_array::SparseArray;
 
+/// A function scoring the entries of a trie, for `Trie::prefix_top_k_scored()`.
+pub type ScoreFn<TK, TV> = fn(&TK, &TV) -> u64;
+
 #[derive(Clone, Debug)]
 pub struct InternalNode<TK: PartialEq + AsRef<[u8]>, TV> {
     pub index: usize,
     pub nibbles: SparseArray<Node<TK, TV>>,
+    /// The number of leaves in this subtree.
+    #[cfg(feature = "subtree-counts")]
+    count: usize,
+    /// The highest score of the leaves in this subtree.
+    #[cfg(feature = "subtree-scores")]
+    max_score: u64,
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> InternalNode<TK, TV> {
+    /// Creates an internal node out of `nibbles`, computing its number of
+    /// leaves, and its highest score if the trie has a scoring function.
+    #[inline]
+    pub fn new(
+        index: usize,
+        nibbles: SparseArray<Node<TK, TV>>,
+        score: Option<ScoreFn<TK, TV>>,
+    ) -> Self {
+        let mut internal = InternalNode {
+            index,
+            nibbles,
+            #[cfg(feature = "subtree-counts")]
+            count: 0,
+            #[cfg(feature = "subtree-scores")]
+            max_score: 0,
+        };
+        internal.recount();
+        if let Some(score) = score {
+            internal.rescore(score);
+        }
+        internal
+    }
+
+    /// Returns the number of leaves in this subtree.
+    #[cfg(feature = "subtree-counts")]
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the number of leaves in this subtree.
+    #[cfg(not(feature = "subtree-counts"))]
+    pub fn count(&self) -> usize {
+        self.nibbles.all().iter().map(Node::count).sum()
+    }
+
+    /// Updates the number of leaves in this subtree after its children have
+    /// changed.
+    #[cfg(feature = "subtree-counts")]
+    #[inline]
+    pub fn recount(&mut self) {
+        self.count = self.nibbles.all().iter().map(Node::count).sum();
+    }
+
+    /// Updates the number of leaves in this subtree after its children have
+    /// changed.
+    #[cfg(not(feature = "subtree-counts"))]
+    #[inline]
+    pub fn recount(&mut self) {}
+
+    /// Returns the highest score of the leaves in this subtree.
+    #[cfg(feature = "subtree-scores")]
+    #[inline]
+    pub fn max_score(&self) -> u64 {
+        self.max_score
+    }
+
+    /// Returns an upper bound of the scores of the leaves in this subtree,
+    /// which is not tracked without the `subtree-scores` feature.
+    #[cfg(not(feature = "subtree-scores"))]
+    #[inline]
+    pub fn max_score(&self) -> u64 {
+        u64::MAX
+    }
+
+    /// Updates the highest score of the leaves in this subtree after its
+    /// children have changed.
+    #[cfg(feature = "subtree-scores")]
+    pub fn rescore(&mut self, score: ScoreFn<TK, TV>) {
+        self.max_score = self
+            .nibbles
+            .all()
+            .iter()
+            .map(|child| child.max_score(score))
+            .max()
+            .unwrap_or(0);
+    }
+
+    /// Updates the highest score of the leaves in this subtree after its
+    /// children have changed.
+    #[cfg(not(feature = "subtree-scores"))]
+    #[inline]
+    pub fn rescore(&mut self, _score: ScoreFn<TK, TV>) {}
+}
+
+/// The internal nodes walked through from the root to reach a node, whose
+/// number of leaves and highest score change when leaves are added or removed
+/// below that node.
+///
+/// Without the `subtree-counts` and `subtree-scores` features, nothing is
+/// recorded.
+pub struct Ancestors<TK: PartialEq + AsRef<[u8]>, TV> {
+    #[cfg(any(feature = "subtree-counts", feature = "subtree-scores"))]
+    nodes: Vec<*mut Node<TK, TV>>,
+    #[cfg(not(any(feature = "subtree-counts", feature = "subtree-scores")))]
+    marker: PhantomData<*mut Node<TK, TV>>,
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> Ancestors<TK, TV> {
+    #[cfg(any(feature = "subtree-counts", feature = "subtree-scores"))]
+    #[inline]
+    pub fn new() -> Self {
+        Ancestors { nodes: vec![] }
+    }
+
+    #[cfg(not(any(feature = "subtree-counts", feature = "subtree-scores")))]
+    #[inline]
+    pub fn new() -> Self {
+        Ancestors {
+            marker: PhantomData,
+        }
+    }
+
+    /// Records the internal node `t`, below the previously recorded ones.
+    #[cfg(any(feature = "subtree-counts", feature = "subtree-scores"))]
+    #[inline]
+    pub fn push(&mut self, t: *mut Node<TK, TV>) {
+        self.nodes.push(t);
+    }
+
+    /// Records the internal node `t`, below the previously recorded ones.
+    #[cfg(not(any(feature = "subtree-counts", feature = "subtree-scores")))]
+    #[inline]
+    pub fn push(&mut self, _t: *mut Node<TK, TV>) {}
+
+    /// Accounts for `count` leaves added below the recorded nodes.
+    #[cfg(feature = "subtree-counts")]
+    pub fn add(&self, count: usize) {
+        for &t in &self.nodes {
+            unsafe { (*t).as_mut_internal().count += count };
+        }
+    }
+
+    /// Accounts for `count` leaves added below the recorded nodes.
+    #[cfg(not(feature = "subtree-counts"))]
+    #[inline]
+    pub fn add(&self, _count: usize) {}
+
+    /// Accounts for `count` leaves removed below the recorded nodes.
+    ///
+    /// This has to be done before a node gets collapsed into its remaining
+    /// child.
+    #[cfg(feature = "subtree-counts")]
+    pub fn sub(&self, count: usize) {
+        for &t in &self.nodes {
+            unsafe { (*t).as_mut_internal().count -= count };
+        }
+    }
+
+    /// Accounts for `count` leaves removed below the recorded nodes.
+    #[cfg(not(feature = "subtree-counts"))]
+    #[inline]
+    pub fn sub(&self, _count: usize) {}
+
+    /// Updates the highest score of the recorded nodes, from the bottom up,
+    /// after leaves have been added, removed or modified below them.
+    ///
+    /// This has to be done after a node gets collapsed into its remaining
+    /// child, which may then be a leaf.
+    #[cfg(feature = "subtree-scores")]
+    pub fn rescore(&self, score: Option<ScoreFn<TK, TV>>) {
+        let score = match score {
+            None => return,
+            Some(score) => score,
+        };
+        for &t in self.nodes.iter().rev() {
+            unsafe {
+                if let Node::Internal(ref mut internal) = *t {
+                    internal.rescore(score);
+                }
+            }
+        }
+    }
+
+    /// Updates the highest score of the recorded nodes, from the bottom up,
+    /// after leaves have been added, removed or modified below them.
+    #[cfg(not(feature = "subtree-scores"))]
+    #[inline]
+    pub fn rescore(&self, _score: Option<ScoreFn<TK, TV>>) {}
 }
 
 #[derive(Clone, Debug)]
@@ -63,6 +257,69 @@ impl<TK: PartialEq + AsRef<[u8]>, TV> Node<TK, TV> {
         }
     }
 
+    #[inline]
+    pub fn into_leaf(self) -> LeafNode<TK, TV> {
+        match self {
+            Node::Leaf(leaf) => leaf,
+            _ => unsafe { debug_unreachable!() },
+        }
+    }
+
+    /// Returns the number of leaves in this subtree.
+    #[inline]
+    pub fn count(&self) -> usize {
+        match *self {
+            Node::Empty => 0,
+            Node::Leaf(_) => 1,
+            Node::Internal(ref internal) => internal.count(),
+        }
+    }
+
+    /// Returns the highest score of the leaves in this subtree, or an upper
+    /// bound of it without the `subtree-scores` feature.
+    #[inline]
+    pub fn max_score(&self, score: ScoreFn<TK, TV>) -> u64 {
+        match *self {
+            Node::Empty => 0,
+            Node::Leaf(ref leaf) => score(&leaf.key, &leaf.val),
+            Node::Internal(ref internal) => internal.max_score(),
+        }
+    }
+
+    /// Updates the highest score of every internal node of this subtree.
+    #[cfg(feature = "subtree-scores")]
+    pub fn rescore_all(&mut self, score: ScoreFn<TK, TV>) {
+        if let Node::Internal(ref mut internal) = *self {
+            for child in internal.nibbles.all_mut() {
+                child.rescore_all(score);
+            }
+            internal.rescore(score);
+        }
+    }
+
+    /// Updates the highest score of every internal node of this subtree.
+    #[cfg(not(feature = "subtree-scores"))]
+    #[inline]
+    pub fn rescore_all(&mut self, _score: ScoreFn<TK, TV>) {}
+
+    /// Returns the leaf with the smallest key in this subtree.
+    pub fn first_leaf(&self) -> &LeafNode<TK, TV> {
+        let mut t = self;
+        while let Node::Internal(ref internal) = *t {
+            t = internal.nibbles.head();
+        }
+        t.as_leaf()
+    }
+
+    /// Returns the leaf with the greatest key in this subtree.
+    pub fn last_leaf(&self) -> &LeafNode<TK, TV> {
+        let mut t = self;
+        while let Node::Internal(ref internal) = *t {
+            t = internal.nibbles.tail();
+        }
+        t.as_leaf()
+    }
+
     #[inline]
     pub fn is_empty(&self) -> bool {
         matches!(*self, Node::Empty)
@@ -0,0 +1,514 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::iter::{FromIterator, FusedIterator};
+
+use super::entry::Entry;
+use super::error::InsertError;
+use super::iterator::{
+    Difference, Intersection, IntoIter, Keys, Range, SymmetricDifference, TriePrefixIterator, Union,
+};
+use super::key_range::KeyRange;
+use super::Trie;
+
+/// A qp-trie based set of keys.
+///
+/// This is a `Trie` with `()` values, which take no space in the leaves.
+#[derive(Clone, Debug)]
+pub struct TrieSet<TK: PartialEq + AsRef<[u8]>> {
+    trie: Trie<TK, ()>,
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> Default for TrieSet<TK> {
+    fn default() -> Self {
+        TrieSet { trie: Trie::new() }
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> PartialEq for TrieSet<TK> {
+    fn eq(&self, other: &Self) -> bool {
+        self.trie == other.trie
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> Eq for TrieSet<TK> {}
+
+impl<TK: PartialEq + AsRef<[u8]>> PartialOrd for TrieSet<TK> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> Ord for TrieSet<TK> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.trie.cmp(&other.trie)
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> Hash for TrieSet<TK> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.trie.hash(state);
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> TrieSet<TK> {
+    /// Creates a new, empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuses to insert keys that would make the underlying trie height
+    /// greater than `max_height`.
+    pub fn max_height(self, max_height: usize) -> Self {
+        TrieSet {
+            trie: self.trie.max_height(max_height),
+        }
+    }
+
+    /// Returns the underlying trie.
+    #[inline]
+    pub fn as_trie(&self) -> &Trie<TK, ()> {
+        &self.trie
+    }
+
+    /// Returns `true` if the set is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.trie.is_empty()
+    }
+
+    /// Returns the number of keys in the set.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.trie.len()
+    }
+
+    /// Removes all the keys from the set.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.trie.clear()
+    }
+
+    /// Adds the key `key` to the set.
+    ///
+    /// Returns `true` if the key was not already present. If it was, the
+    /// stored key is left untouched.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the insertion would make the set higher than its
+    /// `max_height`; use `try_insert()` to handle that case instead.
+    pub fn insert(&mut self, key: TK) -> bool {
+        match self.try_insert(key) {
+            Ok(inserted) => inserted,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Adds the key `key` to the set.
+    ///
+    /// Returns `true` if the key was not already present, or an error holding
+    /// the key if the insertion was refused.
+    pub fn try_insert(&mut self, key: TK) -> Result<bool, InsertError<TK, ()>> {
+        match self.trie.entry(key) {
+            Entry::Occupied(_) => Ok(false),
+            Entry::Vacant(entry) => entry.try_insert(()).map(|_| true),
+        }
+    }
+
+    /// Returns `true` if the set contains the key `key`.
+    #[inline]
+    pub fn contains<Q: ?Sized + AsRef<[u8]>>(&self, key: &Q) -> bool {
+        self.trie.contains_key(key)
+    }
+
+    /// Returns the stored key equal to `key`, if any.
+    pub fn get<Q: ?Sized + AsRef<[u8]>>(&self, key: &Q) -> Option<&TK> {
+        self.trie.get_key_value(key).map(|(key, _)| key)
+    }
+
+    /// Removes the key `key` from the set, and returns `true` if it was
+    /// present.
+    pub fn remove<Q: ?Sized + AsRef<[u8]>>(&mut self, key: &Q) -> bool {
+        self.trie.remove(key).is_some()
+    }
+
+    /// Removes the key `key` from the set, and returns the stored key.
+    pub fn take<Q: ?Sized + AsRef<[u8]>>(&mut self, key: &Q) -> Option<TK> {
+        self.trie.remove_entry(key).map(|(key, _)| key)
+    }
+
+    /// Removes all the keys starting with `prefix`, and returns how many were
+    /// removed.
+    pub fn remove_prefix<Q: ?Sized + AsRef<[u8]>>(&mut self, prefix: &Q) -> usize {
+        self.trie.remove_prefix(prefix)
+    }
+
+    /// Keeps only the keys for which `f` returns `true`.
+    pub fn retain<F: FnMut(&TK) -> bool>(&mut self, mut f: F) {
+        self.trie.retain(|key, _| f(key))
+    }
+
+    /// Returns the smallest key of the set.
+    pub fn first(&self) -> Option<&TK> {
+        self.trie.first_key_value().map(|(key, _)| key)
+    }
+
+    /// Returns the largest key of the set.
+    pub fn last(&self) -> Option<&TK> {
+        self.trie.last_key_value().map(|(key, _)| key)
+    }
+
+    /// Removes and returns the smallest key of the set.
+    pub fn pop_first(&mut self) -> Option<TK> {
+        self.trie.pop_first().map(|(key, _)| key)
+    }
+
+    /// Removes and returns the largest key of the set.
+    pub fn pop_last(&mut self) -> Option<TK> {
+        self.trie.pop_last().map(|(key, _)| key)
+    }
+
+    /// Moves all the keys greater than or equal to `key` to a new set.
+    pub fn split_off<Q: ?Sized + AsRef<[u8]>>(&mut self, key: &Q) -> Self {
+        TrieSet {
+            trie: self.trie.split_off(key),
+        }
+    }
+
+    /// Moves all the keys of `other` into the set, leaving `other` empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the merged set would be higher than the `max_height` of this
+    /// set, in which case neither set is modified.
+    pub fn append(&mut self, other: &mut Self) {
+        self.trie.append(&mut other.trie)
+    }
+
+    /// Returns an iterator over the keys in the set or in `other`, in
+    /// lexicographic order.
+    pub fn union<'t>(&'t self, other: &'t Self) -> SetUnion<'t, TK> {
+        SetUnion {
+            iter: self.trie.union(&other.trie),
+        }
+    }
+
+    /// Returns an iterator over the keys in both the set and `other`, in
+    /// lexicographic order.
+    pub fn intersection<'t>(&'t self, other: &'t Self) -> SetIntersection<'t, TK> {
+        SetIntersection {
+            iter: self.trie.intersection(&other.trie),
+        }
+    }
+
+    /// Returns an iterator over the keys in the set but not in `other`, in
+    /// lexicographic order.
+    pub fn difference<'t>(&'t self, other: &'t Self) -> SetDifference<'t, TK> {
+        SetDifference {
+            iter: self.trie.difference(&other.trie),
+        }
+    }
+
+    /// Returns an iterator over the keys in either the set or `other`, but not
+    /// in both, in lexicographic order.
+    pub fn symmetric_difference<'t>(&'t self, other: &'t Self) -> SetSymmetricDifference<'t, TK> {
+        SetSymmetricDifference {
+            iter: self.trie.symmetric_difference(&other.trie),
+        }
+    }
+
+    /// Returns `true` if the set and `other` have no keys in common.
+    pub fn is_disjoint(&self, other: &Self) -> bool {
+        self.intersection(other).next().is_none()
+    }
+
+    /// Returns `true` if all the keys of the set are in `other`.
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.len() <= other.len() && self.difference(other).next().is_none()
+    }
+
+    /// Returns `true` if all the keys of `other` are in the set.
+    pub fn is_superset(&self, other: &Self) -> bool {
+        other.is_subset(self)
+    }
+
+    /// Returns an iterator over the keys of the set, in lexicographic order.
+    pub fn iter(&self) -> SetIter<'_, TK> {
+        SetIter {
+            keys: self.trie.keys(),
+        }
+    }
+
+    /// Returns an iterator over the keys starting with `prefix`.
+    pub fn prefix_iter<Q: ?Sized + AsRef<[u8]>>(&self, prefix: &Q) -> SetPrefixIter<'_, TK> {
+        SetPrefixIter {
+            iter: self.trie.prefix_iter(prefix),
+        }
+    }
+
+    /// Returns an iterator over the keys within `range`, in lexicographic
+    /// order.
+    ///
+    /// `range` is given as with `Trie::range()`.
+    ///
+    /// Panics if the start of the range is greater than its end.
+    pub fn range<R: KeyRange>(&self, range: R) -> SetRange<'_, TK> {
+        SetRange {
+            range: self.trie.range(range),
+        }
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> From<Trie<TK, ()>> for TrieSet<TK> {
+    fn from(trie: Trie<TK, ()>) -> Self {
+        TrieSet { trie }
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> From<TrieSet<TK>> for Trie<TK, ()> {
+    fn from(set: TrieSet<TK>) -> Self {
+        set.trie
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, const N: usize> From<[TK; N]> for TrieSet<TK> {
+    fn from(keys: [TK; N]) -> Self {
+        IntoIterator::into_iter(keys).collect()
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> FromIterator<TK> for TrieSet<TK> {
+    fn from_iter<I: IntoIterator<Item = TK>>(iter: I) -> Self {
+        let mut set = TrieSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> Extend<TK> for TrieSet<TK> {
+    fn extend<I: IntoIterator<Item = TK>>(&mut self, iter: I) {
+        for key in iter {
+            self.insert(key);
+        }
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> IntoIterator for TrieSet<TK> {
+    type Item = TK;
+    type IntoIter = SetIntoIter<TK>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        SetIntoIter {
+            iter: self.trie.into_iter(),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> IntoIterator for &'t TrieSet<TK> {
+    type Item = &'t TK;
+    type IntoIter = SetIter<'t, TK>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// An iterator over the keys of a set, in lexicographic order.
+#[derive(Clone, Debug)]
+pub struct SetIter<'t, TK: 't + PartialEq + AsRef<[u8]>> {
+    keys: Keys<'t, TK, ()>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> Iterator for SetIter<'t, TK> {
+    type Item = &'t TK;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.keys.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> DoubleEndedIterator for SetIter<'t, TK> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.keys.next_back()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> ExactSizeIterator for SetIter<'t, TK> {}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> FusedIterator for SetIter<'t, TK> {}
+
+/// An iterator over the keys of a set starting with a prefix.
+#[derive(Clone, Debug)]
+pub struct SetPrefixIter<'t, TK: 't + PartialEq + AsRef<[u8]>> {
+    iter: TriePrefixIterator<'t, TK, ()>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> SetPrefixIter<'t, TK> {
+    /// If a key equal to the prefix itself is found, include it in the results.
+    #[inline]
+    pub fn include_prefix(self) -> Self {
+        SetPrefixIter {
+            iter: self.iter.include_prefix(),
+        }
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> Iterator for SetPrefixIter<'t, TK> {
+    type Item = &'t TK;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(key, _)| key)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> DoubleEndedIterator for SetPrefixIter<'t, TK> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.iter.next_back().map(|(key, _)| key)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> FusedIterator for SetPrefixIter<'t, TK> {}
+
+/// An iterator over a range of keys of a set.
+#[derive(Clone, Debug)]
+pub struct SetRange<'t, TK: 't + PartialEq + AsRef<[u8]>> {
+    range: Range<'t, TK, ()>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> Iterator for SetRange<'t, TK> {
+    type Item = &'t TK;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.range.next().map(|(key, _)| key)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> DoubleEndedIterator for SetRange<'t, TK> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.range.next_back().map(|(key, _)| key)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> FusedIterator for SetRange<'t, TK> {}
+
+/// An owning iterator over the keys of a set, in lexicographic order.
+#[derive(Debug)]
+pub struct SetIntoIter<TK: PartialEq + AsRef<[u8]>> {
+    iter: IntoIter<TK, ()>,
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> Iterator for SetIntoIter<TK> {
+    type Item = TK;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(key, _)| key)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> DoubleEndedIterator for SetIntoIter<TK> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.iter.next_back().map(|(key, _)| key)
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>> ExactSizeIterator for SetIntoIter<TK> {}
+
+impl<TK: PartialEq + AsRef<[u8]>> FusedIterator for SetIntoIter<TK> {}
+
+/// An iterator over the keys in either of two sets, in lexicographic order.
+#[derive(Clone, Debug)]
+pub struct SetUnion<'t, TK: 't + PartialEq + AsRef<[u8]>> {
+    iter: Union<'t, TK, ()>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> Iterator for SetUnion<'t, TK> {
+    type Item = &'t TK;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(key, _)| key)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> FusedIterator for SetUnion<'t, TK> {}
+
+/// An iterator over the keys in both of two sets, in lexicographic order.
+#[derive(Clone, Debug)]
+pub struct SetIntersection<'t, TK: 't + PartialEq + AsRef<[u8]>> {
+    iter: Intersection<'t, TK, ()>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> Iterator for SetIntersection<'t, TK> {
+    type Item = &'t TK;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(key, _)| key)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> FusedIterator for SetIntersection<'t, TK> {}
+
+/// An iterator over the keys of a set that are not in another set, in
+/// lexicographic order.
+#[derive(Clone, Debug)]
+pub struct SetDifference<'t, TK: 't + PartialEq + AsRef<[u8]>> {
+    iter: Difference<'t, TK, ()>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> Iterator for SetDifference<'t, TK> {
+    type Item = &'t TK;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(key, _)| key)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> FusedIterator for SetDifference<'t, TK> {}
+
+/// An iterator over the keys in exactly one of two sets, in lexicographic
+/// order.
+#[derive(Clone, Debug)]
+pub struct SetSymmetricDifference<'t, TK: 't + PartialEq + AsRef<[u8]>> {
+    iter: SymmetricDifference<'t, TK, ()>,
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> Iterator for SetSymmetricDifference<'t, TK> {
+    type Item = &'t TK;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(key, _)| key)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>> FusedIterator for SetSymmetricDifference<'t, TK> {}
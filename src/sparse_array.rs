@@ -1,3 +1,5 @@
+use std::{slice, vec};
+
 type Bitmap = u32;
 
 #[derive(Clone, Debug)]
@@ -53,11 +55,15 @@ impl<TI> SparseArray<TI> {
         }
     }
 
+    /// Returns a mutable reference to the item at `sparse_index`.
+    ///
+    /// The reference is derived without borrowing the other items, so that
+    /// pointers to them remain valid.
     #[inline]
     pub fn get_mut(&mut self, sparse_index: usize) -> Option<&mut TI> {
         if self.has_sparse_index(sparse_index) {
             let actual_index = self.actual_index(sparse_index);
-            Some(&mut self.array[actual_index])
+            Some(unsafe { &mut *self.array.as_mut_ptr().add(actual_index) })
         } else {
             None
         }
@@ -72,13 +78,51 @@ impl<TI> SparseArray<TI> {
         }
     }
 
+    /// Returns the smallest sparse index greater than or equal to
+    /// `sparse_index` that holds an item.
     #[inline]
-    pub fn get_or_head_mut(&mut self, sparse_index: usize) -> &mut TI {
-        if self.has_sparse_index(sparse_index) {
-            let actual_index = self.actual_index(sparse_index);
-            &mut self.array[actual_index]
+    pub fn next_index(&self, sparse_index: usize) -> Option<usize> {
+        let above = self.bitmap & !((1 << sparse_index) - 1);
+        if above == 0 {
+            None
+        } else {
+            Some(above.trailing_zeros() as usize)
+        }
+    }
+
+    /// Returns the greatest sparse index lower than `sparse_index` that holds
+    /// an item.
+    #[inline]
+    pub fn prev_index(&self, sparse_index: usize) -> Option<usize> {
+        let below = self.bitmap & ((1 << sparse_index) - 1);
+        if below == 0 {
+            None
+        } else {
+            Some((Bitmap::BITS - 1 - below.leading_zeros()) as usize)
+        }
+    }
+
+    /// Returns the item with the greatest sparse index lower than
+    /// `sparse_index`.
+    #[inline]
+    pub fn get_before(&self, sparse_index: usize) -> Option<&TI> {
+        let below = self.bitmap & ((1 << sparse_index) - 1);
+        if below == 0 {
+            None
         } else {
-            self.head_mut()
+            Some(&self.array[below.count_ones() as usize - 1])
+        }
+    }
+
+    /// Returns the item with the smallest sparse index greater than
+    /// `sparse_index`.
+    #[inline]
+    pub fn get_after(&self, sparse_index: usize) -> Option<&TI> {
+        let above = self.bitmap & !((2 << sparse_index) - 1);
+        if above == 0 {
+            None
+        } else {
+            Some(&self.array[self.actual_index(above.trailing_zeros() as usize)])
         }
     }
 
@@ -95,11 +139,17 @@ impl<TI> SparseArray<TI> {
         }
     }
 
-    pub fn remove(&mut self, sparse_index: usize) {
+    pub fn remove(&mut self, sparse_index: usize) -> TI {
         debug_assert!(self.has_sparse_index(sparse_index));
         self.bitmap &= !(1 << sparse_index);
         let actual_index = self.actual_index(sparse_index);
-        self.array.remove(actual_index);
+        self.array.remove(actual_index)
+    }
+
+    #[inline]
+    pub fn head_index(&self) -> usize {
+        debug_assert!(!self.array.is_empty());
+        self.bitmap.trailing_zeros() as usize
     }
 
     #[inline]
@@ -108,6 +158,18 @@ impl<TI> SparseArray<TI> {
         &self.array[0]
     }
 
+    #[inline]
+    pub fn tail_index(&self) -> usize {
+        debug_assert!(!self.array.is_empty());
+        Self::bitmap_size() - 1 - self.bitmap.leading_zeros() as usize
+    }
+
+    #[inline]
+    pub fn tail(&self) -> &TI {
+        debug_assert!(!self.array.is_empty());
+        &self.array[self.array.len() - 1]
+    }
+
     #[inline]
     pub fn head_mut(&mut self) -> &mut TI {
         debug_assert!(!self.array.is_empty());
@@ -127,6 +189,32 @@ impl<TI> SparseArray<TI> {
         &self.array
     }
 
+    #[inline]
+    pub fn all_mut(&mut self) -> &mut [TI] {
+        &mut self.array
+    }
+
+    #[inline]
+    pub fn into_vec(self) -> Vec<TI> {
+        self.array
+    }
+
+    #[inline]
+    pub fn entries(&self) -> Entries<slice::Iter<'_, TI>> {
+        Entries {
+            items: self.array.iter(),
+            bitmap: self.bitmap,
+        }
+    }
+
+    #[inline]
+    pub fn into_entries(self) -> Entries<vec::IntoIter<TI>> {
+        Entries {
+            items: self.array.into_iter(),
+            bitmap: self.bitmap,
+        }
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         debug_assert_eq!(self.bitmap.count_ones() as usize, self.array.len());
@@ -144,3 +232,31 @@ impl<TI> SparseArray<TI> {
         self.array.clear();
     }
 }
+
+/// An iterator over the `(sparse index, item)` pairs of a sparse array.
+pub struct Entries<I> {
+    items: I,
+    bitmap: Bitmap,
+}
+
+impl<I: Iterator> Iterator for Entries<I> {
+    type Item = (usize, I::Item);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.items.next()?;
+        let sparse_index = self.bitmap.trailing_zeros() as usize;
+        self.bitmap &= self.bitmap - 1;
+        Some((sparse_index, item))
+    }
+}
+
+impl<I: DoubleEndedIterator> DoubleEndedIterator for Entries<I> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let item = self.items.next_back()?;
+        let sparse_index = (Bitmap::BITS - 1 - self.bitmap.leading_zeros()) as usize;
+        self.bitmap &= !(1 << sparse_index);
+        Some((sparse_index, item))
+    }
+}
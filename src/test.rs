@@ -1,4 +1,7 @@
-use super::Trie;
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::node::Node;
+use super::{Entry, InsertErrorKind, Trie};
 
 #[test]
 fn test_gen() {
@@ -47,18 +50,18 @@ fn test_gen() {
         assert!(it.next().is_none());
     }
 
-    assert!(!trie.remove(&vec![0xf2, 0x30, 0x42]));
+    assert_eq!(trie.remove(&vec![0xf2, 0x30, 0x42]), None);
     assert_eq!(trie.get(&vec![0x12, 0x30, 0x42]), Some(&"123042"));
-    assert!(trie.remove(&vec![0x12, 0x30, 0x42]));
+    assert_eq!(trie.remove(&vec![0x12, 0x30, 0x42]), Some("123042"));
     assert_eq!(trie.get(&vec![0x12, 0x30, 0x42]), None);
-    assert!(!trie.remove(&vec![0x12, 0x30, 0x42]));
+    assert_eq!(trie.remove(&vec![0x12, 0x30, 0x42]), None);
     assert_eq!(trie.get(&vec![0x12, 0x30, 0x42]), None);
 
     let mut trie2 = Trie::default();
     trie2.insert("x", "x");
     assert_eq!(trie2.get(&"x"), Some(&"x"));
     assert_eq!(trie2.get_mut(&"x"), Some(&mut "x"));
-    assert!(trie2.remove(&"x"));
+    assert_eq!(trie2.remove(&"x"), Some("x"));
     assert_eq!(trie2.get(&"x"), None);
     assert_eq!(trie2.get_mut(&"x"), None);
 
@@ -84,3 +87,1595 @@ fn test_gen() {
     trie3.remove(&"z");
     assert!(trie3.is_empty());
 }
+
+#[test]
+fn test_entry() {
+    let mut trie = Trie::default();
+    for word in &["abc", "abd", "abc", "b", "abc", ""] {
+        *trie.entry(*word).or_insert(0) += 1;
+    }
+    assert_eq!(trie.get(&"abc"), Some(&3));
+    assert_eq!(trie.get(&"abd"), Some(&1));
+    assert_eq!(trie.get(&"b"), Some(&1));
+    assert_eq!(trie.get(&""), Some(&1));
+
+    trie.entry("abd").and_modify(|v| *v += 10).or_insert(0);
+    trie.entry("abe").and_modify(|v| *v += 10).or_insert(42);
+    assert_eq!(trie.get(&"abd"), Some(&11));
+    assert_eq!(trie.get(&"abe"), Some(&42));
+
+    match trie.entry("b") {
+        Entry::Occupied(mut entry) => {
+            assert_eq!(*entry.key(), "b");
+            assert_eq!(entry.insert(7), 1);
+            assert_eq!(*entry.get(), 7);
+        }
+        Entry::Vacant(_) => panic!("entry should be occupied"),
+    }
+    match trie.entry("c") {
+        Entry::Occupied(_) => panic!("entry should be vacant"),
+        Entry::Vacant(entry) => {
+            assert_eq!(*entry.key(), "c");
+            *entry.insert(1) += 1;
+        }
+    }
+    assert_eq!(trie.get(&"c"), Some(&2));
+    assert_eq!(*trie.entry("d").or_default(), 0);
+    assert_eq!(*trie.entry("ab").or_insert_with_key(|k| k.len()), 2);
+    assert_eq!(trie.get(&"ab"), Some(&2));
+}
+
+#[test]
+fn test_insert_result() {
+    let mut trie = Trie::new().max_height(1);
+    assert_eq!(trie.insert("a", 1), None);
+    assert_eq!(trie.insert("a", 2), Some(1));
+    assert_eq!(trie.try_insert("b", 3), Ok(None));
+    assert_eq!(trie.try_insert("b", 4), Ok(Some(3)));
+    let err = trie.try_insert("bb", 5).unwrap_err();
+    assert_eq!(err.kind, InsertErrorKind::MaxHeight);
+    assert_eq!(err.into_inner(), ("bb", 5));
+    assert_eq!(trie.get(&"bb"), None);
+    match trie.entry("bb") {
+        Entry::Vacant(entry) => assert!(entry.try_insert(5).is_err()),
+        Entry::Occupied(_) => panic!("entry should be vacant"),
+    }
+    assert_eq!(trie.get(&"a"), Some(&2));
+    assert_eq!(trie.get(&"b"), Some(&4));
+}
+
+#[test]
+#[should_panic]
+fn test_insert_too_high() {
+    let mut trie = Trie::new().max_height(1);
+    trie.insert("a", 1);
+    trie.insert("b", 2);
+    trie.insert("bb", 3);
+}
+
+#[test]
+fn test_remove_entry() {
+    let mut trie = Trie::new();
+    trie.insert(String::from("abc"), vec![1]);
+    trie.insert(String::from("abd"), vec![2]);
+    trie.insert(String::from("b"), vec![3]);
+    assert_eq!(trie.remove_entry(&String::from("ab")), None);
+    assert_eq!(
+        trie.remove_entry(&String::from("abd")),
+        Some((String::from("abd"), vec![2]))
+    );
+    assert_eq!(trie.get(&String::from("abc")), Some(&vec![1]));
+    match trie.entry(String::from("b")) {
+        Entry::Occupied(entry) => assert_eq!(entry.remove(), vec![3]),
+        Entry::Vacant(_) => panic!("entry should be occupied"),
+    }
+    match trie.entry(String::from("abc")) {
+        Entry::Occupied(entry) => {
+            assert_eq!(entry.remove_entry(), (String::from("abc"), vec![1]))
+        }
+        Entry::Vacant(_) => panic!("entry should be occupied"),
+    }
+    assert!(trie.is_empty());
+}
+
+#[test]
+fn test_len() {
+    let mut trie = Trie::new();
+    assert_eq!(trie.len(), 0);
+    trie.insert("a", 1);
+    trie.insert("ab", 2);
+    trie.insert("b", 3);
+    trie.insert("b", 4);
+    assert_eq!(trie.len(), 3);
+    *trie.entry("c").or_insert(0) += 1;
+    *trie.entry("c").or_insert(0) += 1;
+    assert_eq!(trie.len(), 4);
+    let expected = if cfg!(feature = "subtree-counts") {
+        (2, Some(2))
+    } else {
+        (0, Some(4))
+    };
+    assert_eq!(
+        trie.prefix_iter(&"a").include_prefix().size_hint(),
+        expected
+    );
+    trie.remove(&"x");
+    trie.remove(&"ab");
+    assert_eq!(trie.len(), 3);
+    match trie.entry("a") {
+        Entry::Occupied(entry) => entry.remove(),
+        Entry::Vacant(_) => panic!("entry should be occupied"),
+    };
+    assert_eq!(trie.len(), 2);
+    trie.clear();
+    assert_eq!(trie.len(), 0);
+    assert!(trie.is_empty());
+}
+
+#[test]
+fn test_iterators() {
+    let keys = [
+        vec![0x12, 0x34],
+        vec![],
+        vec![0x12],
+        vec![0x12, 0x30, 0x01],
+        vec![0xff],
+        vec![0x01, 0xff],
+        vec![0x12, 0x30],
+    ];
+    let mut trie = Trie::new();
+    for (i, key) in keys.iter().enumerate() {
+        trie.insert(key.clone(), i);
+    }
+    let mut sorted = keys.to_vec();
+    sorted.sort();
+
+    assert_eq!(trie.iter().len(), keys.len());
+    assert_eq!(trie.keys().cloned().collect::<Vec<_>>(), sorted);
+    let values: Vec<usize> = sorted
+        .iter()
+        .map(|k| keys.iter().position(|x| x == k).unwrap())
+        .collect();
+    assert_eq!(trie.values().cloned().collect::<Vec<_>>(), values);
+    for (k, v) in &trie {
+        assert_eq!(keys[*v], *k);
+    }
+
+    for (_, v) in trie.iter_mut() {
+        *v += 100;
+    }
+    for v in trie.values_mut() {
+        *v += 100;
+    }
+    for (_, v) in &mut trie {
+        *v -= 200;
+    }
+    assert_eq!(trie.values().cloned().collect::<Vec<_>>(), values);
+
+    let mut cloned = trie.clone();
+    let drained: Vec<_> = cloned.drain().collect();
+    assert!(cloned.is_empty());
+    assert_eq!(cloned.len(), 0);
+    assert_eq!(cloned.iter().next(), None);
+
+    let owned: Vec<_> = trie.into_iter().collect();
+    assert_eq!(owned, drained);
+    assert_eq!(
+        owned.into_iter().map(|(k, _)| k).collect::<Vec<_>>(),
+        sorted
+    );
+}
+
+fn pseudo_random_keys(count: usize, seed: u32) -> Vec<Vec<u8>> {
+    let mut state = seed;
+    let mut next = move || {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (state >> 16) as u8
+    };
+    (0..count)
+        .map(|_| {
+            let len = (next() % 5) as usize;
+            (0..len).map(|_| next() % 4 * 0x11).collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_double_ended() {
+    let mut trie = Trie::new();
+    let mut keys = pseudo_random_keys(500, 1);
+    for key in &keys {
+        trie.insert(key.clone(), key.len());
+    }
+    keys.sort();
+    keys.dedup();
+
+    let rev: Vec<_> = trie.keys().rev().cloned().collect();
+    let mut expected = keys.clone();
+    expected.reverse();
+    assert_eq!(rev, expected);
+    assert_eq!(
+        trie.clone()
+            .into_iter()
+            .rev()
+            .map(|(k, _)| k)
+            .collect::<Vec<_>>(),
+        expected
+    );
+
+    let mut it = trie.iter();
+    let mut front = vec![];
+    let mut back = vec![];
+    loop {
+        assert_eq!(it.len(), keys.len() - front.len() - back.len());
+        match it.next() {
+            None => break,
+            Some((k, _)) => front.push(k.clone()),
+        }
+        if let Some((k, _)) = it.next_back() {
+            back.push(k.clone());
+        }
+    }
+    back.reverse();
+    front.extend(back);
+    assert_eq!(front, keys);
+
+    for prefix in &[vec![], vec![0x11], vec![0x22, 0x33], vec![0x21]] {
+        let expected: Vec<_> = keys
+            .iter()
+            .filter(|k| k.starts_with(prefix) && k.len() > prefix.len())
+            .cloned()
+            .collect();
+        let found: Vec<_> = trie.prefix_iter(prefix).map(|(k, _)| k.clone()).collect();
+        assert_eq!(found, expected);
+        let mut found: Vec<_> = trie
+            .prefix_iter(prefix)
+            .rev()
+            .map(|(k, _)| k.clone())
+            .collect();
+        found.reverse();
+        assert_eq!(found, expected);
+    }
+
+    for v in trie.values_mut().rev().take(3) {
+        *v = 100;
+    }
+    assert_eq!(
+        trie.values().rev().take(4).filter(|&&v| v == 100).count(),
+        3
+    );
+}
+
+#[test]
+fn test_borrowed_lookups() {
+    let mut trie: Trie<Vec<u8>, u32> = Trie::new();
+    trie.insert(b"abc".to_vec(), 1);
+    trie.insert(b"abd".to_vec(), 2);
+    assert_eq!(trie.get(b"abc"), Some(&1));
+    assert_eq!(trie.get("abd"), Some(&2));
+    assert_eq!(trie.get(&b"abd"[..]), Some(&2));
+    assert!(trie.contains_key("abc"));
+    assert!(!trie.contains_key("ab"));
+    *trie.get_mut("abc").unwrap() += 10;
+    assert_eq!(trie.prefix_iter("ab").count(), 2);
+    assert_eq!(
+        trie.prefix_iter(b"abc").include_prefix().next(),
+        Some((&b"abc".to_vec(), &11))
+    );
+    assert_eq!(trie.remove("abc"), Some(11));
+    assert_eq!(trie.remove_entry(b"abd"), Some((b"abd".to_vec(), 2)));
+
+    let mut trie: Trie<String, u32> = Trie::new();
+    trie.insert(String::from("x"), 1);
+    assert_eq!(trie.get("x"), Some(&1));
+    assert_eq!(trie.remove("x"), Some(1));
+    assert!(trie.is_empty());
+}
+
+#[test]
+fn test_range() {
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+
+    let keys = pseudo_random_keys(400, 2);
+    let mut trie = Trie::new();
+    let mut map = BTreeMap::new();
+    for (i, key) in keys.iter().enumerate() {
+        trie.insert(key.clone(), i);
+        map.insert(key.clone(), i);
+    }
+    let mut bounds: Vec<Vec<u8>> = pseudo_random_keys(30, 3);
+    bounds.push(vec![]);
+    bounds.push(vec![0xff, 0xff]);
+    bounds.push(vec![0x11, 0x10]);
+    let kinds = |b: &Vec<u8>| {
+        vec![
+            Bound::Included(b.clone()),
+            Bound::Excluded(b.clone()),
+            Bound::Unbounded,
+        ]
+    };
+    for lo in &bounds {
+        for hi in &bounds {
+            if lo > hi {
+                continue;
+            }
+            for lower in kinds(lo) {
+                for upper in kinds(hi) {
+                    if let (Bound::Excluded(a), Bound::Excluded(b)) = (&lower, &upper) {
+                        if a == b {
+                            continue;
+                        }
+                    }
+                    let range = (lower.clone(), upper.clone());
+                    let expected: Vec<_> = map.range(range.clone()).collect();
+                    let found: Vec<_> = trie.range(range.clone()).collect();
+                    assert_eq!(found, expected);
+                    let mut found: Vec<_> = trie.range(range.clone()).rev().collect();
+                    found.reverse();
+                    assert_eq!(found, expected);
+                }
+            }
+        }
+    }
+
+    let mut trie2: Trie<&str, u32> = Trie::new();
+    for (i, k) in ["a", "ab", "abc", "b", "ba", "c"].iter().enumerate() {
+        trie2.insert(*k, i as u32);
+    }
+    let found: Vec<_> = trie2.range("ab".."b").map(|(k, _)| *k).collect();
+    assert_eq!(found, vec!["ab", "abc"]);
+    let found: Vec<_> = trie2.range("ab"..="ba").map(|(k, _)| *k).collect();
+    assert_eq!(found, vec!["ab", "abc", "b", "ba"]);
+    for (_, v) in trie2.range_mut("b"..) {
+        *v += 10;
+    }
+    assert_eq!(trie2.get("a"), Some(&0));
+    assert_eq!(trie2.get("b"), Some(&13));
+    assert_eq!(trie2.get("c"), Some(&15));
+    assert_eq!(trie2.range(..).count(), 6);
+    assert_eq!(trie2.range_mut(..).count(), 6);
+}
+
+#[test]
+#[should_panic]
+fn test_range_reversed() {
+    let trie: Trie<&str, ()> = Trie::new();
+    trie.range("b".."a");
+}
+
+#[test]
+fn test_longest_prefix() {
+    let mut trie = Trie::new();
+    trie.insert("10.", 1);
+    trie.insert("10.0.", 2);
+    trie.insert("10.0.0.1", 3);
+    trie.insert("10.1.", 4);
+    trie.insert("192.168.", 5);
+    assert_eq!(trie.longest_prefix("10.0.0.1"), Some((&"10.0.0.1", &3)));
+    assert_eq!(trie.longest_prefix("10.0.0.2"), Some((&"10.0.", &2)));
+    assert_eq!(trie.longest_prefix("10.0.0.10"), Some((&"10.0.0.1", &3)));
+    assert_eq!(trie.longest_prefix("10.2.0.1"), Some((&"10.", &1)));
+    assert_eq!(trie.longest_prefix("10"), None);
+    assert_eq!(trie.longest_prefix("192.168.1.1"), Some((&"192.168.", &5)));
+    assert_eq!(trie.longest_prefix("172.16.0.1"), None);
+    *trie.longest_prefix_mut("10.1.2.3").unwrap().1 += 10;
+    assert_eq!(trie.get("10.1."), Some(&14));
+    assert_eq!(trie.longest_prefix_mut("9"), None);
+
+    let keys = pseudo_random_keys(300, 4);
+    let mut trie = Trie::new();
+    for key in &keys {
+        trie.insert(key.clone(), ());
+    }
+    for query in pseudo_random_keys(100, 5) {
+        let expected = keys
+            .iter()
+            .filter(|k| query.starts_with(k))
+            .max_by_key(|k| k.len());
+        assert_eq!(trie.longest_prefix(&query).map(|(k, _)| k), expected);
+    }
+}
+
+#[test]
+fn test_prefixes_of() {
+    let mut trie = Trie::new();
+    for key in &["", "a", "a/b", "a/b/c", "a/b/c/d/e", "a/bc", "b"] {
+        trie.insert(*key, key.len());
+    }
+    let found: Vec<_> = trie.prefixes_of("a/b/c/d").map(|(k, _)| *k).collect();
+    assert_eq!(found, vec!["", "a", "a/b", "a/b/c"]);
+    let found: Vec<_> = trie.prefixes_of("a/b/c").map(|(k, _)| *k).collect();
+    assert_eq!(found, vec!["", "a", "a/b", "a/b/c"]);
+    let found: Vec<_> = trie.prefixes_of("c").map(|(k, _)| *k).collect();
+    assert_eq!(found, vec![""]);
+    assert_eq!(Trie::<&str, ()>::new().prefixes_of("x").next(), None);
+
+    let keys = pseudo_random_keys(300, 6);
+    let mut trie = Trie::new();
+    for key in &keys {
+        trie.insert(key.clone(), ());
+    }
+    for query in pseudo_random_keys(100, 7) {
+        let mut expected: Vec<_> = keys.iter().filter(|k| query.starts_with(k)).collect();
+        expected.sort();
+        expected.dedup();
+        let found: Vec<_> = trie.prefixes_of(&query).map(|(k, _)| k).collect();
+        assert_eq!(found, expected);
+    }
+}
+
+#[test]
+fn test_neighbours() {
+    use std::collections::BTreeSet;
+    use std::ops::Bound;
+
+    let keys = pseudo_random_keys(300, 8);
+    let mut trie = Trie::new();
+    for key in &keys {
+        trie.insert(key.clone(), ());
+    }
+    let set: BTreeSet<_> = keys.iter().cloned().collect();
+    let mut queries = pseudo_random_keys(200, 9);
+    queries.extend(keys.iter().take(50).cloned());
+    queries.push(vec![0xff]);
+    for query in &queries {
+        let floor = set.range(..=query.clone()).next_back();
+        let ceiling = set.range(query.clone()..).next();
+        let predecessor = set.range(..query.clone()).next_back();
+        let successor = set
+            .range((Bound::Excluded(query.clone()), Bound::Unbounded))
+            .next();
+        assert_eq!(trie.floor(query).map(|(k, _)| k), floor);
+        assert_eq!(trie.ceiling(query).map(|(k, _)| k), ceiling);
+        assert_eq!(trie.predecessor(query).map(|(k, _)| k), predecessor);
+        assert_eq!(trie.successor(query).map(|(k, _)| k), successor);
+    }
+
+    let mut trie = Trie::new();
+    assert_eq!(trie.floor("a"), None);
+    trie.insert("b", 1);
+    trie.insert("d", 2);
+    assert_eq!(trie.floor("c"), Some((&"b", &1)));
+    assert_eq!(trie.ceiling("c"), Some((&"d", &2)));
+    assert_eq!(trie.floor("b"), Some((&"b", &1)));
+    assert_eq!(trie.predecessor("b"), None);
+    assert_eq!(trie.successor("d"), None);
+    assert_eq!(trie.successor("a"), Some((&"b", &1)));
+}
+
+#[test]
+fn test_first_last() {
+    let mut trie = Trie::new();
+    assert_eq!(trie.first_key_value(), None);
+    assert_eq!(trie.pop_last(), None);
+    let mut keys = pseudo_random_keys(200, 10);
+    for key in &keys {
+        trie.insert(key.clone(), key.len());
+    }
+    keys.sort();
+    keys.dedup();
+    assert_eq!(trie.first_key_value().map(|(k, _)| k), keys.first());
+    assert_eq!(trie.last_key_value().map(|(k, _)| k), keys.last());
+    let mut popped = vec![];
+    let mut popped_back = vec![];
+    while !trie.is_empty() {
+        popped.push(trie.pop_first().unwrap().0);
+        if let Some((k, v)) = trie.pop_last() {
+            assert_eq!(k.len(), v);
+            popped_back.push(k);
+        }
+        assert_eq!(trie.len(), keys.len() - popped.len() - popped_back.len());
+    }
+    popped_back.reverse();
+    popped.extend(popped_back);
+    assert_eq!(popped, keys);
+    assert_eq!(trie.pop_first(), None);
+}
+
+#[test]
+fn test_prefix_iter_mut() {
+    let mut trie = Trie::new();
+    for key in &["a", "ab", "abc", "abd", "b", "ba"] {
+        trie.insert(*key, 0);
+    }
+    for (_, v) in trie.prefix_iter_mut("ab") {
+        *v += 1;
+    }
+    for (_, v) in trie.prefix_iter_mut("ab").include_prefix().rev() {
+        *v += 10;
+    }
+    for (_, v) in trie.prefix_iter_mut("x") {
+        *v += 100;
+    }
+    let values: Vec<_> = trie.iter().map(|(k, v)| (*k, *v)).collect();
+    assert_eq!(
+        values,
+        vec![
+            ("a", 0),
+            ("ab", 10),
+            ("abc", 11),
+            ("abd", 11),
+            ("b", 0),
+            ("ba", 0)
+        ]
+    );
+}
+
+#[test]
+fn test_remove_prefix() {
+    let keys = pseudo_random_keys(400, 11);
+    let mut trie = Trie::new();
+    for key in &keys {
+        trie.insert(key.clone(), ());
+    }
+    let mut expected: Vec<_> = trie.keys().cloned().collect();
+    for prefix in &[
+        vec![0x11, 0x22],
+        vec![0x33],
+        vec![0x12],
+        vec![0x00, 0x00, 0x00],
+    ] {
+        let removed: Vec<_> = expected
+            .iter()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        expected.retain(|k| !k.starts_with(prefix));
+        let mut copy = trie.clone();
+        assert_eq!(trie.remove_prefix(prefix), removed.len());
+        let split = copy.split_off_prefix(prefix);
+        assert_eq!(split.len(), removed.len());
+        assert_eq!(split.keys().cloned().collect::<Vec<_>>(), removed);
+        for t in &[&trie, &copy] {
+            assert_eq!(t.len(), expected.len());
+            assert_eq!(t.keys().cloned().collect::<Vec<_>>(), expected);
+        }
+        for key in &removed {
+            assert!(!trie.contains_key(key));
+        }
+    }
+    let everything = trie.split_off_prefix(&[]);
+    assert!(trie.is_empty());
+    assert_eq!(trie.len(), 0);
+    assert_eq!(everything.len(), expected.len());
+    assert_eq!(trie.remove_prefix(&[]), 0);
+}
+
+fn check_node(node: &Node<Vec<u8>, usize>, min_index: usize) -> usize {
+    match *node {
+        Node::Leaf(_) => 1,
+        Node::Internal(ref internal) => {
+            assert!(internal.index >= min_index);
+            assert!(internal.nibbles.len() >= 2);
+            let mut count = 0;
+            for child in internal.nibbles.all() {
+                let key = child.first_leaf().key.clone();
+                assert!(child
+                    .last_leaf()
+                    .key
+                    .starts_with(&key[..internal.index / 2]));
+                count += check_node(child, internal.index + 1);
+            }
+            assert_eq!(node.count(), count);
+            count
+        }
+        Node::Empty => panic!("empty node in the trie"),
+    }
+}
+
+#[cfg(feature = "subtree-scores")]
+fn check_scores(node: &Node<Vec<u8>, usize>) -> u64 {
+    match *node {
+        Node::Internal(ref internal) => {
+            let max_score = internal.nibbles.all().iter().map(check_scores).max();
+            assert_eq!(Some(internal.max_score()), max_score);
+            internal.max_score()
+        }
+        _ => node.max_score(|_, val| *val as u64),
+    }
+}
+
+fn height(node: &Node<Vec<u8>, usize>) -> usize {
+    match *node {
+        Node::Internal(ref internal) => {
+            1 + internal.nibbles.all().iter().map(height).max().unwrap()
+        }
+        _ => 0,
+    }
+}
+
+fn check_invariants(trie: &Trie<Vec<u8>, usize>) {
+    let count = trie.root().map_or(0, |root| check_node(root, 0));
+    assert_eq!(count, trie.len());
+    let keys: Vec<_> = trie.keys().collect();
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[test]
+fn test_retain() {
+    let keys = pseudo_random_keys(500, 12);
+    let mut trie = Trie::new();
+    for (i, key) in keys.iter().enumerate() {
+        trie.insert(key.clone(), i);
+    }
+    let all: Vec<_> = trie.iter().map(|(k, v)| (k.clone(), *v)).collect();
+
+    let mut retained = trie.clone();
+    retained.retain(|_, v| *v % 3 != 0);
+    check_invariants(&retained);
+    let expected: Vec<_> = all.iter().filter(|&&(_, v)| v % 3 != 0).cloned().collect();
+    assert_eq!(
+        retained
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect::<Vec<_>>(),
+        expected
+    );
+
+    let mut extracted = trie.clone();
+    let removed: Vec<_> = extracted.extract_if(|k, _| k.len() == 2).collect();
+    check_invariants(&extracted);
+    let (two, others): (Vec<_>, Vec<_>) = all.iter().cloned().partition(|(k, _)| k.len() == 2);
+    assert_eq!(removed, two);
+    assert_eq!(
+        extracted
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect::<Vec<_>>(),
+        others
+    );
+
+    let mut partial = trie.clone();
+    let removed: Vec<_> = partial.extract_if(|_, v| *v % 2 == 0).take(10).collect();
+    assert_eq!(removed.len(), 10);
+    check_invariants(&partial);
+    assert_eq!(partial.len(), all.len() - 10);
+    for (k, _) in &removed {
+        assert!(!partial.contains_key(k));
+    }
+
+    let mut leaked = trie.clone();
+    let mut iter = leaked.extract_if(|_, v| *v % 2 == 0);
+    assert_eq!(iter.by_ref().take(10).count(), 10);
+    ::std::mem::forget(iter);
+    check_invariants(&leaked);
+    assert_eq!(leaked.len(), all.len() - 10);
+
+    trie.retain(|_, _| false);
+    assert!(trie.is_empty());
+    assert_eq!(trie.len(), 0);
+    let mut single = Trie::new();
+    single.insert(vec![1], 1);
+    assert_eq!(
+        single.extract_if(|_, _| true).collect::<Vec<_>>(),
+        vec![(vec![1], 1)]
+    );
+    assert!(single.is_empty());
+    for keys in &[
+        vec![vec![1], vec![2]],
+        vec![vec![0x10], vec![0x11], vec![0x20]],
+    ] {
+        let mut collapsing: Trie<_, _> = keys.iter().map(|k| (k.clone(), ())).collect();
+        let removed: Vec<_> = collapsing.extract_if(|_, _| true).map(|(k, _)| k).collect();
+        assert_eq!(&removed, keys);
+        assert!(collapsing.is_empty());
+    }
+}
+
+#[test]
+fn test_split_off_merge() {
+    let keys = pseudo_random_keys(500, 16);
+    let mut trie = Trie::new();
+    for (i, key) in keys.iter().enumerate() {
+        trie.insert(key.clone(), i);
+    }
+    let all: Vec<_> = trie.iter().map(|(k, v)| (k.clone(), *v)).collect();
+
+    for split in &[
+        vec![],
+        vec![0x11],
+        vec![0x11, 0x22],
+        vec![0x33, 0x33, 0x33, 0x33, 0x33],
+    ] {
+        let mut below = trie.clone();
+        let above = below.split_off(split);
+        check_invariants(&below);
+        check_invariants(&above);
+        let (expected_above, expected_below): (Vec<_>, Vec<_>) =
+            all.iter().cloned().partition(|(k, _)| k >= split);
+        assert_eq!(
+            below
+                .iter()
+                .map(|(k, v)| (k.clone(), *v))
+                .collect::<Vec<_>>(),
+            expected_below
+        );
+        assert_eq!(
+            above
+                .iter()
+                .map(|(k, v)| (k.clone(), *v))
+                .collect::<Vec<_>>(),
+            expected_above
+        );
+
+        let mut rejoined = above.clone();
+        let mut other = below.clone();
+        rejoined.append(&mut other);
+        assert!(other.is_empty());
+        check_invariants(&rejoined);
+        assert_eq!(
+            rejoined
+                .iter()
+                .map(|(k, v)| (k.clone(), *v))
+                .collect::<Vec<_>>(),
+            all
+        );
+    }
+
+    let mut evens = Trie::new();
+    let mut odds = Trie::new();
+    for (i, key) in pseudo_random_keys(300, 17).into_iter().enumerate() {
+        if i % 2 == 0 {
+            evens.insert(key, 1);
+        } else {
+            odds.insert(key, 10);
+        }
+    }
+    let mut expected = evens.clone();
+    for (k, v) in odds.iter() {
+        *expected.entry(k.clone()).or_insert(0) += *v;
+    }
+    evens.merge_with(odds, |_, a, b| a + b);
+    check_invariants(&evens);
+    assert_eq!(
+        evens
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect::<Vec<_>>(),
+        expected
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect::<Vec<_>>()
+    );
+
+    for seed in 0..20 {
+        let (a, b): (Vec<_>, Vec<_>) = pseudo_random_keys(40, 100 + seed)
+            .into_iter()
+            .zip(0..)
+            .partition(|(_, i)| i % 3 == 0);
+        let (a, b): (Trie<_, _>, Trie<_, _>) = (a.into_iter().collect(), b.into_iter().collect());
+        let mut merged = a.clone();
+        merged.merge_with(b.clone(), |_, _, val| val);
+        let merged_height = merged.root().map_or(0, height);
+        for max_height in merged_height.saturating_sub(1)..=merged_height {
+            let mut limited = a.clone().max_height(max_height);
+            match limited.try_merge_with(b.clone(), |_, _, val| val) {
+                Ok(()) => {
+                    assert!(merged_height <= max_height);
+                    assert_eq!(limited, merged);
+                }
+                Err(other) => {
+                    assert!(merged_height > max_height);
+                    assert_eq!(limited, a);
+                    assert_eq!(other, b);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+#[should_panic]
+fn test_append_too_high() {
+    let mut trie = Trie::new().max_height(1);
+    trie.insert("a", 1);
+    trie.insert("b", 2);
+    let mut other = Trie::from([("bb", 3)]);
+    trie.append(&mut other);
+}
+
+#[test]
+fn test_std_traits() {
+    use std::cmp::Ordering;
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::BTreeMap;
+    use std::hash::{Hash, Hasher};
+
+    fn hash_of(trie: &Trie<Vec<u8>, usize>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        trie.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    let map: BTreeMap<_, _> = pseudo_random_keys(200, 18).into_iter().zip(0..).collect();
+    let forward: Trie<_, _> = map.iter().map(|(k, v)| (k.clone(), *v)).collect();
+    let mut backward = Trie::new().max_height(64);
+    backward.extend(map.iter().rev().map(|(k, v)| (k.clone(), *v)));
+    let from_map = Trie::from(map.clone());
+    assert_eq!(forward, backward);
+    assert_eq!(forward, from_map);
+    assert_eq!(hash_of(&forward), hash_of(&backward));
+    for (k, v) in &map {
+        assert_eq!(forward[k], *v);
+        assert_eq!(forward[k.as_slice()], *v);
+    }
+
+    let small = Trie::from([(vec![1u8], 1usize), (vec![2], 2)]);
+    let mut other = small.clone();
+    assert_eq!(small.cmp(&other), Ordering::Equal);
+    other.insert(vec![1, 0], 3);
+    assert_ne!(small, other);
+    assert!(small > other);
+    other.remove(&[1, 0]);
+    assert_eq!(small, other);
+    *other.get_mut(&[2]).unwrap() = 0;
+    assert!(small > other);
+}
+
+#[test]
+#[should_panic]
+fn test_index_missing() {
+    let trie = Trie::from([("a", 1)]);
+    let _ = trie["b"];
+}
+
+#[test]
+fn test_set() {
+    use std::cmp::Ordering;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::mem::size_of;
+
+    use super::node::LeafNode;
+    use super::TrieSet;
+
+    assert_eq!(size_of::<LeafNode<Vec<u8>, ()>>(), size_of::<Vec<u8>>());
+
+    let keys = pseudo_random_keys(300, 19);
+    let mut set = TrieSet::new();
+    let mut expected = BTreeSet::new();
+    for key in &keys {
+        assert_eq!(set.insert(key.clone()), expected.insert(key.clone()));
+    }
+    assert_eq!(set.len(), expected.len());
+    assert!(set.iter().eq(expected.iter()));
+    assert!(set.iter().rev().eq(expected.iter().rev()));
+    assert!(set.contains(&keys[0]));
+    assert_eq!(set.get(keys[0].as_slice()), Some(&keys[0]));
+
+    let prefix = [0x11u8];
+    assert!(set
+        .prefix_iter(&prefix)
+        .include_prefix()
+        .eq(expected.iter().filter(|k| k.starts_with(&prefix))));
+    assert!(set
+        .prefix_iter(&prefix)
+        .all(|k| k.len() > 1 && k.starts_with(&prefix)));
+    assert!(set
+        .range(&[0x11u8][..]..&[0x22u8][..])
+        .eq(expected.range(vec![0x11u8]..vec![0x22u8])));
+
+    assert!(set.remove(&keys[0]));
+    assert!(!set.remove(&keys[0]));
+    assert!(!set.contains(&keys[0]));
+    expected.remove(&keys[0]);
+
+    let mut above = set.split_off(&[0x22u8]);
+    assert!(above.iter().all(|k| k.as_slice() >= &[0x22u8][..]));
+    set.append(&mut above);
+    assert!(above.is_empty());
+    assert!(set.clone().into_iter().eq(expected.iter().cloned()));
+    assert_eq!(set, expected.iter().cloned().collect());
+
+    /// A key type only implementing the traits required by the trie.
+    #[derive(PartialEq)]
+    struct Bytes(&'static [u8]);
+
+    impl AsRef<[u8]> for Bytes {
+        fn as_ref(&self) -> &[u8] {
+            self.0
+        }
+    }
+
+    let hash = |set: &TrieSet<Bytes>| {
+        let mut hasher = DefaultHasher::new();
+        set.hash(&mut hasher);
+        hasher.finish()
+    };
+    let a = TrieSet::from([Bytes(b"a"), Bytes(b"b")]);
+    let b = TrieSet::from([Bytes(b"b"), Bytes(b"a")]);
+    let c = TrieSet::from([Bytes(b"a"), Bytes(b"c")]);
+    assert!(a == b && a != c);
+    assert_eq!(a.cmp(&b), Ordering::Equal);
+    assert!(a < c);
+    assert_eq!(hash(&a), hash(&b));
+}
+
+#[test]
+fn test_set_algebra() {
+    use super::TrieSet;
+
+    for &(seed_a, seed_b, count_b) in &[(20, 21, 300), (22, 23, 20), (24, 25, 0)] {
+        let a: Trie<_, _> = pseudo_random_keys(300, seed_a)
+            .into_iter()
+            .map(|k| (k, 1))
+            .collect();
+        let b: Trie<_, _> = pseudo_random_keys(count_b, seed_b)
+            .into_iter()
+            .map(|k| (k, 2))
+            .collect();
+        let keys_a: BTreeSet<_> = a.keys().cloned().collect();
+        let keys_b: BTreeSet<_> = b.keys().cloned().collect();
+
+        let union: Vec<_> = a.union(&b).map(|(k, v)| (k.clone(), *v)).collect();
+        let expected: Vec<_> = keys_a
+            .union(&keys_b)
+            .map(|k| (k.clone(), if keys_a.contains(k) { 1 } else { 2 }))
+            .collect();
+        assert_eq!(union, expected);
+        assert!(a
+            .intersection(&b)
+            .map(|(k, _)| k)
+            .eq(keys_a.intersection(&keys_b)));
+        assert!(a.intersection(&b).all(|(_, v)| *v == 1));
+        assert!(a
+            .difference(&b)
+            .map(|(k, _)| k)
+            .eq(keys_a.difference(&keys_b)));
+        assert!(b
+            .difference(&a)
+            .map(|(k, _)| k)
+            .eq(keys_b.difference(&keys_a)));
+        assert!(a
+            .symmetric_difference(&b)
+            .map(|(k, _)| k)
+            .eq(keys_a.symmetric_difference(&keys_b)));
+        assert!(b.union(&a).map(|(k, _)| k).eq(keys_b.union(&keys_a)));
+
+        let set_a: TrieSet<_> = keys_a.iter().cloned().collect();
+        let set_b: TrieSet<_> = keys_b.iter().cloned().collect();
+        assert!(set_a.union(&set_b).eq(keys_a.union(&keys_b)));
+        assert!(set_a.intersection(&set_b).eq(keys_a.intersection(&keys_b)));
+        assert!(set_a.difference(&set_b).eq(keys_a.difference(&keys_b)));
+        assert!(set_a
+            .symmetric_difference(&set_b)
+            .eq(keys_a.symmetric_difference(&keys_b)));
+        assert_eq!(set_a.is_disjoint(&set_b), keys_a.is_disjoint(&keys_b));
+        assert_eq!(set_b.is_subset(&set_a), keys_b.is_subset(&keys_a));
+        assert!(set_a.is_superset(&set_a.difference(&set_b).cloned().collect()));
+    }
+}
+
+#[test]
+fn test_order_statistics() {
+    let keys = pseudo_random_keys(400, 26);
+    let mut trie = Trie::new();
+    for (i, key) in keys.iter().enumerate() {
+        trie.insert(key.clone(), i);
+    }
+    for (i, key) in keys.iter().enumerate().step_by(3) {
+        if i % 2 == 0 {
+            trie.remove(key);
+        }
+    }
+    check_invariants(&trie);
+    let sorted: Vec<_> = trie.keys().cloned().collect();
+    for (position, key) in sorted.iter().enumerate() {
+        assert_eq!(trie.select(position).map(|(k, _)| k), Some(key));
+        assert_eq!(trie.rank(key), position);
+    }
+    assert_eq!(trie.select(sorted.len()), None);
+    for key in pseudo_random_keys(200, 27) {
+        assert_eq!(trie.rank(&key), sorted.iter().filter(|k| **k < key).count());
+        assert_eq!(
+            trie.count_prefix(&key),
+            sorted.iter().filter(|k| k.starts_with(&key)).count()
+        );
+    }
+    assert_eq!(trie.count_prefix(&[]), trie.len());
+    assert_eq!(Trie::<Vec<u8>, usize>::new().rank(&[1u8]), 0);
+    for key in sorted.iter().step_by(25) {
+        check_size_hint(trie.prefix_iter(key));
+        check_size_hint(trie.prefix_iter(&key[..1]).include_prefix());
+        check_size_hint(trie.range(key.as_slice()..));
+        check_size_hint(trie.range(..=key.as_slice()));
+    }
+    let count = trie.count_prefix(&sorted[0][..1]);
+    let hint = trie
+        .prefix_iter_mut(&sorted[0][..1])
+        .include_prefix()
+        .size_hint();
+    assert!(hint.0 <= count && hint.1 >= Some(count));
+    let count = trie.range_mut(..sorted[1].as_slice()).count();
+    let hint = trie.range_mut(..sorted[1].as_slice()).size_hint();
+    assert!(hint.0 <= count && hint.1 >= Some(count));
+    if cfg!(feature = "subtree-counts") {
+        assert_eq!(hint, (count, Some(count)));
+    }
+
+    let mut other = trie.split_off(&[0x22u8]);
+    check_invariants(&trie);
+    check_invariants(&other);
+    other.remove_prefix(&[0x33u8, 0x33]);
+    check_invariants(&other);
+    trie.retain(|_, v| *v % 5 != 0);
+    check_invariants(&trie);
+    trie.append(&mut other);
+    check_invariants(&trie);
+    while trie.len() > 10 {
+        trie.pop_first();
+        trie.pop_last();
+    }
+    check_invariants(&trie);
+}
+
+fn check_size_hint<I: Iterator + Clone>(mut iter: I) {
+    loop {
+        let (lower, upper) = iter.size_hint();
+        let count = iter.clone().count();
+        assert!(lower <= count && upper.is_some_and(|upper| upper >= count));
+        if cfg!(feature = "subtree-counts") {
+            assert_eq!(lower, count);
+        }
+        if iter.next().is_none() {
+            break;
+        }
+    }
+}
+
+fn check_top_k(trie: &Trie<Vec<u8>, usize>) {
+    let favourite = 6;
+    for prefix in &[vec![], vec![0x11], vec![0x22, 0x33], vec![0x44]] {
+        for &k in &[0, 1, 5, 1000] {
+            let top = trie.prefix_top_k(prefix, k, |_, val| *val as u64);
+            let mut expected: Vec<_> = trie
+                .iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .collect();
+            expected.sort_by(|a, b| b.1.cmp(a.1));
+            expected.truncate(k);
+            assert_eq!(top, expected);
+            #[cfg(feature = "subtree-scores")]
+            assert_eq!(trie.prefix_top_k_scored(prefix, k), Some(expected));
+
+            let top = trie.prefix_top_k(prefix, k, |_, val| (*val == favourite) as u64);
+            let mut expected: Vec<_> = trie
+                .iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .collect();
+            expected.sort_by_key(|(_, val)| **val != favourite);
+            expected.truncate(k);
+            assert_eq!(top, expected);
+        }
+    }
+}
+
+#[test]
+fn test_prefix_top_k() {
+    let keys = pseudo_random_keys(300, 28);
+    let mut trie = Trie::new();
+    #[cfg(feature = "subtree-scores")]
+    {
+        assert_eq!(trie.prefix_top_k_scored(&[], 1), None);
+        trie = trie.scored_by(|_, val| *val as u64);
+    }
+    for (i, key) in keys.iter().enumerate() {
+        trie.insert(key.clone(), i * 7 % 13);
+    }
+    check_top_k(&trie);
+    for (i, key) in keys.iter().enumerate().step_by(3) {
+        trie.insert(key.clone(), i % 17);
+    }
+    check_top_k(&trie);
+    for key in keys.iter().step_by(4) {
+        trie.remove(key);
+    }
+    trie.remove_prefix(&[0x11, 0x22]);
+    check_top_k(&trie);
+    for val in trie.values_mut().step_by(5) {
+        *val += 10;
+    }
+    check_top_k(&trie);
+    #[cfg(feature = "subtree-scores")]
+    trie.rescore();
+    check_top_k(&trie);
+    let other = trie.split_off(&[0x80]);
+    #[cfg(feature = "subtree-scores")]
+    for t in trie.root().into_iter().chain(other.root()) {
+        check_scores(t);
+    }
+    check_top_k(&trie);
+    check_top_k(&other);
+}
+
+#[cfg(feature = "subtree-scores")]
+#[test]
+fn test_subtree_scores() {
+    let check = |trie: &Trie<Vec<u8>, usize>| {
+        check_scores(trie.root().unwrap());
+        check_top_k(trie);
+    };
+    let keys = pseudo_random_keys(300, 28);
+    let mut trie = Trie::new().scored_by(|_, val| *val as u64);
+    for (i, key) in keys.iter().enumerate() {
+        trie.insert(key.clone(), i * 7 % 13);
+    }
+    check(&trie);
+
+    assert_eq!(trie.get_mut(&[0xff; 9]), None);
+    match trie.entry(keys[0].clone()) {
+        Entry::Occupied(mut entry) => assert!(entry.insert(100) < 13),
+        Entry::Vacant(_) => panic!("entry should be occupied"),
+    }
+    trie.entry(keys[1].clone()).and_modify(|val| *val += 200);
+    check(&trie);
+    trie.retain(|_, val| {
+        *val += 1;
+        *val % 5 != 0
+    });
+    check(&trie);
+    let removed = trie
+        .extract_if(|_, val| {
+            *val += 7;
+            *val % 2 == 0
+        })
+        .take(10)
+        .count();
+    assert_eq!(removed, 10);
+    check(&trie);
+
+    let mut other = trie.split_off(&[0x22]);
+    check(&trie);
+    check(&other);
+    for (i, key) in keys.iter().enumerate().step_by(7) {
+        other.insert(key.clone(), i % 19);
+    }
+    trie.merge_with(other, |_, a, b| a + b);
+    check(&trie);
+
+    // Subtrees moved from a trie scored by another function are not trusted.
+    let mut other = Trie::new();
+    other.insert(vec![0x44, 0xee, 0x01], 1000);
+    other.insert(vec![0x44, 0xee, 0x02], 1001);
+    trie.merge_with(other, |_, a, b| a + b);
+    check_top_k(&trie);
+    trie.rescore();
+    check(&trie);
+}
+
+fn levenshtein(a: &[u8], b: &[u8]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, &x) in a.iter().enumerate() {
+        let previous = row.clone();
+        row[0] = i + 1;
+        for (j, &y) in b.iter().enumerate() {
+            row[j + 1] = (previous[j + 1] + 1)
+                .min(row[j] + 1)
+                .min(previous[j] + (x != y) as usize);
+        }
+    }
+    row[b.len()]
+}
+
+#[test]
+fn test_fuzzy_iter() {
+    let keys = pseudo_random_keys(300, 29);
+    let mut trie = Trie::new();
+    for (i, key) in keys.iter().enumerate() {
+        trie.insert(key.clone(), i);
+    }
+    for query in pseudo_random_keys(30, 30) {
+        for max_distance in 0..3 {
+            let found: Vec<_> = trie
+                .fuzzy_iter(&query, max_distance)
+                .map(|(k, v, d)| (k.clone(), *v, d))
+                .collect();
+            let expected: Vec<_> = trie
+                .iter()
+                .map(|(k, v)| (k.clone(), *v, levenshtein(k, &query)))
+                .filter(|&(_, _, d)| d <= max_distance)
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    let mut hosts = Trie::new();
+    for host in &["example.com", "example.org", "exemple.com", "sample.com"] {
+        hosts.insert(*host, ());
+    }
+    let found: Vec<_> = hosts
+        .fuzzy_iter("exampel.com", 2)
+        .map(|(k, _, d)| (*k, d))
+        .collect();
+    assert_eq!(found, vec![("example.com", 2)]);
+    let found: Vec<_> = hosts
+        .fuzzy_iter("exampel.com", 3)
+        .map(|(k, _, d)| (*k, d))
+        .collect();
+    assert_eq!(found, vec![("example.com", 2), ("exemple.com", 3)]);
+}
+
+/// A backtracking glob matcher supporting `?`, `*` and simple classes.
+fn glob_matches(pattern: &[u8], key: &[u8]) -> bool {
+    match pattern.first() {
+        None => key.is_empty(),
+        Some(&b'*') => (0..=key.len()).any(|skip| glob_matches(&pattern[1..], &key[skip..])),
+        Some(&b'[') => {
+            let end = pattern.iter().position(|&b| b == b']').unwrap();
+            let (negated, class) = match pattern[1] {
+                b'!' => (true, &pattern[2..end]),
+                _ => (false, &pattern[1..end]),
+            };
+            !key.is_empty()
+                && class.contains(&key[0]) != negated
+                && glob_matches(&pattern[end + 1..], &key[1..])
+        }
+        Some(&b'?') => !key.is_empty() && glob_matches(&pattern[1..], &key[1..]),
+        Some(&byte) => key.first() == Some(&byte) && glob_matches(&pattern[1..], &key[1..]),
+    }
+}
+
+#[test]
+fn test_glob() {
+    let keys = pseudo_random_keys(400, 31);
+    let mut trie = Trie::new();
+    for (i, key) in keys.iter().enumerate() {
+        trie.insert(key.clone(), i);
+    }
+    let patterns: &[&[u8]] = &[
+        b"",
+        b"*",
+        b"??",
+        b"\x11*",
+        b"?\x22*",
+        b"*\x33",
+        b"\x11\x22",
+        b"\x11*\x22?",
+        b"[\x00\x11]?*",
+        b"[!\x11]*\x22",
+        b"**\x33*\x00",
+    ];
+    for pattern in patterns {
+        let found: Vec<_> = trie.glob(pattern).map(|(k, v)| (k.clone(), *v)).collect();
+        let expected: Vec<_> = trie
+            .iter()
+            .filter(|(k, _)| glob_matches(pattern, k))
+            .map(|(k, v)| (k.clone(), *v))
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    let mut sessions = Trie::new();
+    for key in &[
+        "user:1:session:ab",
+        "user:1:session:abc",
+        "user:22:session:xy",
+        "user:22:profile",
+        "user:x",
+        "user:3:session:[?",
+    ] {
+        sessions.insert(*key, ());
+    }
+    let keys: Vec<_> = sessions
+        .glob("user:*:session:??")
+        .map(|(k, _)| *k)
+        .collect();
+    assert_eq!(
+        keys,
+        vec![
+            "user:1:session:ab",
+            "user:22:session:xy",
+            "user:3:session:[?"
+        ]
+    );
+    let keys: Vec<_> = sessions
+        .glob("user:[0-9]*:session:[a-c]?")
+        .map(|(k, _)| *k)
+        .collect();
+    assert_eq!(keys, vec!["user:1:session:ab"]);
+    let keys: Vec<_> = sessions.glob("user:[!0-9]").map(|(k, _)| *k).collect();
+    assert_eq!(keys, vec!["user:x"]);
+    let keys: Vec<_> = sessions.glob("*:[\\[]\\?").map(|(k, _)| *k).collect();
+    assert_eq!(keys, vec!["user:3:session:[?"]);
+    let keys: Vec<_> = sessions.glob("*:[?").map(|(k, _)| *k).collect();
+    assert_eq!(keys, vec!["user:3:session:[?"]);
+}
+
+#[test]
+fn test_search() {
+    use super::Automaton;
+
+    /// Accepts the keys with exactly `count` occurrences of `byte`.
+    struct Occurrences {
+        byte: u8,
+        count: usize,
+    }
+
+    impl Automaton for Occurrences {
+        type State = usize;
+
+        fn start(&self) -> usize {
+            0
+        }
+
+        fn transition(&self, &seen: &usize, byte: u8) -> usize {
+            seen + (byte == self.byte) as usize
+        }
+
+        fn is_match(&self, &seen: &usize) -> bool {
+            seen == self.count
+        }
+
+        fn can_match(&self, &seen: &usize) -> bool {
+            seen <= self.count
+        }
+    }
+
+    let keys = pseudo_random_keys(400, 32);
+    let mut trie = Trie::new();
+    for (i, key) in keys.iter().enumerate() {
+        trie.insert(key.clone(), i);
+    }
+    for byte in &[0x00u8, 0x11, 0x44] {
+        for count in 0..3 {
+            let automaton = Occurrences { byte: *byte, count };
+            let found: Vec<_> = trie.search(&automaton).collect();
+            let expected: Vec<_> = trie
+                .iter()
+                .filter(|(k, _)| k.iter().filter(|b| *b == byte).count() == count)
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    /// Accepts the keys starting with `prefix`, hinting at its bytes.
+    struct StartsWith<'p> {
+        prefix: &'p [u8],
+    }
+
+    impl<'p> Automaton for StartsWith<'p> {
+        type State = Option<usize>;
+
+        fn start(&self) -> Option<usize> {
+            Some(0)
+        }
+
+        fn transition(&self, &read: &Option<usize>, byte: u8) -> Option<usize> {
+            match read {
+                Some(read) if read == self.prefix.len() => Some(read),
+                Some(read) if self.prefix[read] == byte => Some(read + 1),
+                _ => None,
+            }
+        }
+
+        fn is_match(&self, &read: &Option<usize>) -> bool {
+            read == Some(self.prefix.len())
+        }
+
+        fn can_match(&self, read: &Option<usize>) -> bool {
+            read.is_some()
+        }
+
+        fn next_bytes(&self, &read: &Option<usize>) -> Option<Vec<u8>> {
+            match read {
+                Some(read) if read < self.prefix.len() => Some(vec![self.prefix[read]]),
+                _ => None,
+            }
+        }
+    }
+
+    trie.insert(vec![0x11, 0x22], 1000);
+    for prefix in &[&[][..], &[0x11], &[0x11, 0x22], &[0x33, 0x44, 0x55]] {
+        let found: Vec<_> = trie.search(StartsWith { prefix }).collect();
+        let expected: Vec<_> = trie.prefix_iter(prefix).include_prefix().collect();
+        assert_eq!(found, expected);
+    }
+
+    /// Accepts nothing, and cannot match from the start.
+    struct Nothing;
+
+    impl Automaton for Nothing {
+        type State = ();
+
+        fn start(&self) {}
+
+        fn transition(&self, _: &(), _: u8) {
+            unreachable!()
+        }
+
+        fn is_match(&self, _: &()) -> bool {
+            false
+        }
+
+        fn can_match(&self, _: &()) -> bool {
+            false
+        }
+    }
+
+    assert_eq!(trie.search(Nothing).count(), 0);
+}
+
+#[test]
+fn test_cursor() {
+    use std::ops::Bound::{Excluded, Unbounded};
+
+    let keys = pseudo_random_keys(500, 40);
+    let mut trie = Trie::new();
+    let mut map = BTreeMap::new();
+    for (i, key) in keys.iter().enumerate() {
+        trie.insert(key.clone(), i);
+        map.insert(key.clone(), i);
+    }
+    let expected: Vec<_> = map.iter().collect();
+
+    let mut cursor = trie.cursor();
+    assert_eq!(cursor.key(), None);
+    let forward: Vec<_> = cursor.by_ref().collect();
+    assert_eq!(forward, expected);
+    assert_eq!(cursor.next(), None);
+    let mut backward = vec![];
+    while let Some(entry) = cursor.prev() {
+        backward.push(entry);
+    }
+    backward.reverse();
+    assert_eq!(backward, expected);
+    assert_eq!(cursor.key(), None);
+    assert_eq!(cursor.next(), expected.first().cloned());
+
+    for probe in pseudo_random_keys(200, 41) {
+        cursor.seek(&probe);
+        assert_eq!(cursor.key_value(), map.range(probe.clone()..).next());
+        cursor.prev();
+        assert_eq!(cursor.key_value(), map.range(..probe).next_back());
+    }
+
+    let mut cursor = trie.cursor_mut();
+    cursor.next();
+    let mut i = 0;
+    while let Some(key) = cursor.key().cloned() {
+        if i % 3 == 0 {
+            let removed = cursor.remove_current().unwrap();
+            assert_eq!(map.remove(&key), Some(removed.1));
+            assert_eq!(removed.0, key);
+        } else {
+            *cursor.value_mut().unwrap() += 1;
+            *map.get_mut(&key).unwrap() += 1;
+            cursor.next();
+        }
+        assert_eq!(
+            cursor.key(),
+            map.range((Excluded(key), Unbounded)).next().map(|(k, _)| k)
+        );
+        i += 1;
+    }
+    assert_eq!(
+        trie.iter().collect::<Vec<_>>(),
+        map.iter().collect::<Vec<_>>()
+    );
+    check_invariants(&trie);
+
+    for (i, key) in pseudo_random_keys(300, 42).into_iter().enumerate() {
+        let mut cursor = trie.cursor_mut();
+        cursor.seek(&key);
+        if map.contains_key(&key) {
+            let err = cursor.insert_before(key.clone(), i).unwrap_err();
+            assert_eq!(err.kind, InsertErrorKind::Unordered);
+            continue;
+        }
+        let current = cursor.key().cloned();
+        if i % 2 == 0 {
+            cursor.insert_before(key.clone(), i).unwrap();
+            assert_eq!(cursor.prev().map(|(k, _)| k.clone()), Some(key.clone()));
+            cursor.next();
+        } else {
+            cursor.prev();
+            cursor.insert_after(key.clone(), i).unwrap();
+            cursor.next();
+            cursor.next();
+        }
+        assert_eq!(cursor.key().cloned(), current);
+        map.insert(key, i);
+    }
+    assert_eq!(
+        trie.iter().collect::<Vec<_>>(),
+        map.iter().collect::<Vec<_>>()
+    );
+    check_invariants(&trie);
+
+    let mut cursor = trie.cursor_mut();
+    let first = cursor.next().unwrap().0.clone();
+    assert_eq!(
+        cursor.insert_after(first.clone(), 0).unwrap_err().kind,
+        InsertErrorKind::Unordered
+    );
+    assert_eq!(cursor.prev(), None);
+    let err = cursor.insert_before(vec![], 0).unwrap_err();
+    assert_eq!(err.kind, InsertErrorKind::Unordered);
+    cursor.seek(&[0xff]);
+    assert_eq!(cursor.key(), None);
+    assert_eq!(
+        cursor.insert_after(vec![0xff], 0).unwrap_err().kind,
+        InsertErrorKind::Unordered
+    );
+    cursor.insert_before(vec![0xff], 0).unwrap();
+    assert_eq!(cursor.key(), None);
+    assert_eq!(cursor.prev().map(|(k, _)| k.clone()), Some(vec![0xff]));
+    assert_eq!(cursor.remove_current(), Some((vec![0xff], 0)));
+    assert_eq!(cursor.key(), None);
+    check_invariants(&trie);
+
+    let mut trie = Trie::new();
+    let mut cursor = trie.cursor_mut();
+    assert_eq!(cursor.remove_current(), None);
+    cursor.insert_before(vec![1], 1).unwrap();
+    assert_eq!(cursor.key(), None);
+    assert_eq!(
+        cursor.next().map(|(k, v)| (k.clone(), *v)),
+        Some((vec![1], 1))
+    );
+    cursor.insert_after(vec![2], 2).unwrap();
+    cursor.insert_before(vec![0], 0).unwrap();
+    assert_eq!(cursor.remove_current(), Some((vec![1], 1)));
+    assert_eq!(cursor.key(), Some(&vec![2]));
+    assert_eq!(cursor.remove_current(), Some((vec![2], 2)));
+    assert_eq!(cursor.prev().map(|(k, _)| k.clone()), Some(vec![0]));
+    assert_eq!(cursor.remove_current(), Some((vec![0], 0)));
+    assert_eq!(cursor.key(), None);
+    assert!(trie.is_empty());
+
+    let mut trie = Trie::new();
+    let mut cursor = trie.cursor_mut();
+    cursor.insert_after(vec![0x10], 1).unwrap();
+    cursor.insert_after(vec![0x01], 0).unwrap();
+    cursor.insert_before(vec![0x11], 2).unwrap();
+    assert_eq!(cursor.key(), None);
+    assert_eq!(cursor.next().map(|(k, _)| k.clone()), Some(vec![0x01]));
+    cursor.insert_after(vec![0x01, 0x00], 3).unwrap();
+    assert_eq!(cursor.key(), Some(&vec![0x01]));
+    assert_eq!(
+        trie.keys().cloned().collect::<Vec<_>>(),
+        vec![vec![0x01], vec![0x01, 0x00], vec![0x10], vec![0x11]]
+    );
+    check_invariants(&trie);
+}
+
+#[test]
+fn test_cursor_max_height() {
+    let mut trie = Trie::new().max_height(2);
+    for key in [vec![0, 0, 17, 0], vec![0, 16], vec![1, 17]] {
+        trie.insert(key, 0);
+    }
+    let mut other = trie.clone();
+    assert_eq!(
+        other.try_insert(vec![16, 16], 0).unwrap_err().kind,
+        InsertErrorKind::MaxHeight
+    );
+    let mut cursor = trie.cursor_mut();
+    cursor.seek(&[16, 16]);
+    assert_eq!(
+        cursor.insert_before(vec![16, 16], 0).unwrap_err().kind,
+        InsertErrorKind::MaxHeight
+    );
+
+    for max_height in 1..5 {
+        let mut trie = Trie::new().max_height(max_height);
+        for (i, key) in pseudo_random_keys(300, 43).into_iter().enumerate() {
+            if trie.contains_key(&key) {
+                continue;
+            }
+            let mut other = trie.clone();
+            let expected = other.try_insert(key.clone(), i).map_err(|e| e.kind);
+            let mut cursor = trie.cursor_mut();
+            cursor.seek(&key);
+            let inserted = if i % 2 == 0 {
+                cursor.insert_before(key, i)
+            } else {
+                cursor.prev();
+                cursor.insert_after(key, i)
+            };
+            assert_eq!(inserted.map_err(|e| e.kind), expected.map(|_| ()));
+            check_invariants(&trie);
+        }
+    }
+}
@@ -1,17 +1,150 @@
-use std::usize;
-use std::{cmp, mem};
+use std::cmp::Reverse;
+use std::cmp::{self, Ordering};
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::{Bound, Index};
+#[cfg(feature = "subtree-scores")]
+use std::ptr;
 
-use super::iterator::TriePrefixIterator;
-use super::node::{InternalNode, LeafNode, Node};
+use super::automaton::Automaton;
+use super::cursor::{Cursor, CursorMut};
+use super::entry::{Entry, OccupiedEntry, VacantEntry};
+use super::error::{InsertError, InsertErrorKind};
+use super::iterator::{
+    Difference, Drain, ExtractIf, FuzzyIter, GlobIter, Intersection, IntoIter, Iter, IterMut, Keys,
+    PrefixesOf, Range, RangeMut, Search, SymmetricDifference, TriePrefixIterator,
+    TriePrefixIteratorMut, Union, Values, ValuesMut,
+};
+use super::key_range::KeyRange;
+use super::node::{Ancestors, InternalNode, LeafNode, Node, ScoreFn};
 use super::sparse_array::SparseArray;
 
 const COMPLETE_KEY_NIBBLE: usize = 0;
 
+/// How a subtree relates to a range of keys.
+enum RangeClass<'b> {
+    /// None of the keys of the subtree are in the range.
+    Outside,
+    /// All the keys of the subtree are in the range.
+    Inside,
+    /// Some keys may be in the range; the children of the subtree have to be
+    /// checked against the remaining bounds.
+    Partial(Bound<&'b [u8]>, Bound<&'b [u8]>),
+}
+
+/// An internal node being visited by `extract_if()`.
+pub(crate) struct ExtractFrame<TK: PartialEq + AsRef<[u8]>, TV> {
+    node: *mut Node<TK, TV>,
+    /// The nibble of the node in its parent.
+    nibble: usize,
+    /// The next nibble to visit.
+    next: usize,
+}
+
+/// How two subtrees relate to each other.
+pub(crate) enum SubtreeRelation {
+    /// All the keys of the first subtree are lower than the keys of the
+    /// second subtree.
+    Before,
+    /// All the keys of the first subtree are greater than the keys of the
+    /// second subtree.
+    After,
+    /// Both subtrees are leaves with the same key.
+    Same,
+    /// The second subtree falls within a child of the first subtree.
+    Contains,
+    /// The first subtree falls within a child of the second subtree.
+    ContainedIn,
+    /// Both subtrees branch at the same index, and their children with the
+    /// same nibbles have to be compared.
+    Aligned,
+}
+
+/// An entry or a subtree competing for a spot in the results of
+/// `prefix_top_k()`.
+///
+/// Candidates are ordered by score, and then by reverse lexicographic order
+/// of their keys, so that the first keys win ties. The score of a subtree is
+/// the highest score below it, and its key is its smallest one.
+struct Scored<'t, T> {
+    score: u64,
+    key: &'t [u8],
+    item: T,
+}
+
+impl<'t, T> PartialEq for Scored<'t, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'t, T> Eq for Scored<'t, T> {}
+
+impl<'t, T> PartialOrd for Scored<'t, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'t, T> Ord for Scored<'t, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .cmp(&other.score)
+            .then_with(|| other.key.cmp(self.key))
+    }
+}
+
+/// The leaves right below, at, and right above a key.
+type Neighbours<'t, TK, TV> = (
+    Option<&'t LeafNode<TK, TV>>,
+    Option<&'t LeafNode<TK, TV>>,
+    Option<&'t LeafNode<TK, TV>>,
+);
+
+/// The parts of a subtree below and above a split key.
+type SplitNodes<TK, TV> = (Option<Node<TK, TV>>, Option<Node<TK, TV>>);
+
+/// The internal node holding a node, along with the nibble of the node in
+/// its parent, or `None` if the node is the root node.
+pub(crate) type NodeParent<TK, TV> = Option<(*mut Node<TK, TV>, usize)>;
+
+/// The outcome of looking up the insertion point of a key.
+///
+/// The pointers are derived from the `*mut Trie` given to
+/// `find_insert_position()`, and only remain valid as long as the trie is not
+/// accessed through any other pointer or reference.
+///
+/// Each position also records the internal nodes above it, whose number of
+/// leaves changes if a leaf is added or removed there.
+pub(crate) enum InsertPosition<TK: PartialEq + AsRef<[u8]>, TV> {
+    /// The key is already present in this leaf.
+    Occupied(*mut LeafNode<TK, TV>, NodeParent<TK, TV>, Ancestors<TK, TV>),
+    /// The trie is empty.
+    Root,
+    /// The key goes into a free slot of an existing internal node.
+    Child(*mut Node<TK, TV>, usize, Ancestors<TK, TV>),
+    /// The node has to be moved under a new internal node branching at the
+    /// given index, in the slot for the given nibble.
+    Split(*mut Node<TK, TV>, usize, usize, Ancestors<TK, TV>),
+    /// Inserting the key would exceed the maximum height of the trie.
+    TooHigh,
+}
+
 /// A qp-trie.
 #[derive(Clone, Debug)]
 pub struct Trie<TK: PartialEq + AsRef<[u8]>, TV> {
     root: Option<Node<TK, TV>>,
     max_height: usize,
+    len: usize,
+    /// The function whose highest score is stored in every subtree.
+    #[cfg(feature = "subtree-scores")]
+    score: Option<ScoreFn<TK, TV>>,
+    /// Whether values may have been modified since the highest scores of the
+    /// subtrees were last computed.
+    #[cfg(feature = "subtree-scores")]
+    scores_stale: bool,
 }
 
 impl<TK: PartialEq + AsRef<[u8]>, TV> Default for Trie<TK, TV> {
@@ -19,12 +152,17 @@ impl<TK: PartialEq + AsRef<[u8]>, TV> Default for Trie<TK, TV> {
         Trie {
             root: None,
             max_height: usize::MAX,
+            len: 0,
+            #[cfg(feature = "subtree-scores")]
+            score: None,
+            #[cfg(feature = "subtree-scores")]
+            scores_stale: false,
         }
     }
 }
 
 impl<TK: PartialEq + AsRef<[u8]>, TV> Trie<TK, TV> {
-    fn nibble(key: &[u8], index: usize) -> usize {
+    pub(crate) fn nibble(key: &[u8], index: usize) -> usize {
         let key_len = key.len();
         if index / 2 >= key_len {
             COMPLETE_KEY_NIBBLE
@@ -38,25 +176,60 @@ impl<TK: PartialEq + AsRef<[u8]>, TV> Trie<TK, TV> {
         }
     }
 
+    /// Returns the index of the first nibble that differs between `a` and
+    /// `b`, or `None` if the keys are equal.
+    pub(crate) fn critical_index(a: &[u8], b: &[u8]) -> Option<usize> {
+        let mut i = 0;
+        let mut x = 0;
+        let min_keys_len = cmp::min(a.len(), b.len());
+        while i < min_keys_len {
+            x = a[i] ^ b[i];
+            if x != 0 {
+                break;
+            }
+            i += 1;
+        }
+        if x == 0 {
+            if a.len() == b.len() {
+                return None;
+            }
+            x = 0xff;
+        }
+        let mut index = i * 2;
+        if (x & 0xf0) == 0 {
+            index += 1;
+        }
+        Some(index)
+    }
+
     fn find_closest_leaf_mut(
         root: &mut Node<TK, TV>,
         key: &[u8],
-    ) -> (*mut LeafNode<TK, TV>, usize) {
+        ancestors: &mut Ancestors<TK, TV>,
+    ) -> (*mut LeafNode<TK, TV>, usize, NodeParent<TK, TV>) {
         let mut height = 0;
+        let mut parent = None;
         let mut t: *mut Node<TK, TV> = root;
         unsafe {
             while let Node::Internal(ref mut internal) = *t {
                 let internal_index = internal.index;
-                let nibble = Self::nibble(key, internal_index);
-                t = internal.nibbles.get_or_head_mut(nibble);
+                let mut nibble = Self::nibble(key, internal_index);
+                if !internal.nibbles.has_sparse_index(nibble) {
+                    nibble = internal.nibbles.head_index();
+                }
+                ancestors.push(t);
+                parent = Some((t, nibble));
+                t = internal.nibbles.get_mut(nibble).unwrap();
                 height += 1;
             }
-            ((*t).as_mut_leaf(), height)
+            ((*t).as_mut_leaf(), height, parent)
         }
     }
 
-    #[allow(dead_code)]
-    fn find_closest_leaf<'t>(root: &'t Node<TK, TV>, key: &[u8]) -> (&'t LeafNode<TK, TV>, usize) {
+    pub(crate) fn find_closest_leaf<'t>(
+        root: &'t Node<TK, TV>,
+        key: &[u8],
+    ) -> (&'t LeafNode<TK, TV>, usize) {
         let mut height = 0;
         let mut t: &Node<TK, TV> = root;
         while let Node::Internal(ref internal) = *t {
@@ -74,10 +247,7 @@ impl<TK: PartialEq + AsRef<[u8]>, TV> Trie<TK, TV> {
             while let Node::Internal(ref mut internal) = *t {
                 let internal_index = internal.index;
                 let nibble = Self::nibble(key, internal_index);
-                t = match internal.nibbles.get_mut(nibble) {
-                    None => return None,
-                    Some(t) => t,
-                };
+                t = internal.nibbles.get_mut(nibble)?;
             }
             let leaf = (*t).as_mut_leaf();
             if key != leaf.key.as_ref() {
@@ -92,10 +262,7 @@ impl<TK: PartialEq + AsRef<[u8]>, TV> Trie<TK, TV> {
         while let Node::Internal(ref internal) = *t {
             let internal_index = internal.index;
             let nibble = Self::nibble(key, internal_index);
-            t = match internal.nibbles.get(nibble) {
-                None => return None,
-                Some(t) => t,
-            };
+            t = internal.nibbles.get(nibble)?;
         }
         let leaf = t.as_leaf();
         if key != leaf.key.as_ref() {
@@ -104,46 +271,260 @@ impl<TK: PartialEq + AsRef<[u8]>, TV> Trie<TK, TV> {
         Some(leaf)
     }
 
-    fn new_internal_for_shorter_index(
+    /// Moves `t` under a new internal node branching at `index`, next to a
+    /// new leaf, and returns the nibble of the new leaf.
+    fn replace_with_internal_node(
         t: *mut Node<TK, TV>,
-        leaf_key: &TK,
+        orig_nibble: usize,
         index: usize,
         key: TK,
         val: TV,
-    ) {
-        let mut new_internal = InternalNode {
-            nibbles: SparseArray::with_capacity(2),
-            index,
-        };
-        let orig_nibble = Self::nibble(leaf_key.as_ref(), index);
+        score: Option<ScoreFn<TK, TV>>,
+    ) -> usize {
+        let mut nibbles = SparseArray::with_capacity(2);
         let new_nibble = Self::nibble(key.as_ref(), index);
         let new_leaf = Node::Leaf(LeafNode { key, val });
         debug_assert!(orig_nibble != new_nibble);
         let orig_node = unsafe { mem::replace(&mut *t, Node::Empty) };
-        new_internal.nibbles.set(orig_nibble, orig_node);
-        new_internal.nibbles.set(new_nibble, new_leaf);
-        unsafe { *t = Node::Internal(new_internal) };
+        nibbles.set(orig_nibble, orig_node);
+        nibbles.set(new_nibble, new_leaf);
+        unsafe { *t = Node::Internal(InternalNode::new(index, nibbles, score)) };
+        new_nibble
+    }
+
+    /// Locates the leaf holding `key` in `trie`, or the place where it would
+    /// have to be attached if it is not present yet.
+    pub(crate) fn find_insert_position(trie: *mut Self, key: &[u8]) -> InsertPosition<TK, TV> {
+        let root = match unsafe { (*trie).root.as_mut() } {
+            None => return InsertPosition::Root,
+            Some(root) => root,
+        };
+        let mut ancestors = Ancestors::new();
+        let (leaf, height, parent) = unsafe {
+            let closest = Self::find_closest_leaf_mut(root, key, &mut ancestors);
+            (&mut *closest.0, closest.1, closest.2)
+        };
+        let leaf_key = leaf.key.as_ref();
+        let index = match Self::critical_index(key, leaf_key) {
+            None => return InsertPosition::Occupied(leaf, parent, ancestors),
+            Some(index) => index,
+        };
+        let orig_nibble = Self::nibble(leaf_key, index);
+        let mut ancestors = Ancestors::new();
+        let mut t: *mut Node<TK, TV> = root;
+        loop {
+            match *unsafe { &mut *t } {
+                Node::Leaf(_) => break,
+                Node::Internal(ref mut internal) => {
+                    if internal.index > index {
+                        break;
+                    }
+                    ancestors.push(t);
+                    let new_nibble = Self::nibble(key, internal.index);
+                    match internal.nibbles.get_mut(new_nibble) {
+                        Some(t_next) => t = t_next,
+                        None => return InsertPosition::Child(t, new_nibble, ancestors),
+                    }
+                }
+                _ => unsafe { debug_unreachable!() },
+            }
+        }
+        Self::split_position(trie, t, index, orig_nibble, height, ancestors)
     }
 
-    fn replace_leaf_with_internal_node(
+    /// Returns the position moving `t` under a new internal node branching at
+    /// `index`, in the slot for `orig_nibble`, unless the closest leaf is
+    /// already at the maximum height of the trie.
+    pub(crate) fn split_position(
+        trie: *mut Self,
         t: *mut Node<TK, TV>,
-        leaf: &LeafNode<TK, TV>,
         index: usize,
+        orig_nibble: usize,
+        height: usize,
+        ancestors: Ancestors<TK, TV>,
+    ) -> InsertPosition<TK, TV> {
+        if height >= unsafe { (*trie).max_height } {
+            return InsertPosition::TooHigh;
+        }
+        InsertPosition::Split(t, index, orig_nibble, ancestors)
+    }
+
+    /// Attaches a new leaf at a vacant `position` previously returned by
+    /// `find_insert_position()` for `trie`, and returns a pointer to that leaf.
+    ///
+    /// `trie` is never reborrowed as a whole, so that the pointers held by
+    /// `position` stay valid.
+    pub(crate) fn insert_at(
+        trie: *mut Self,
+        position: InsertPosition<TK, TV>,
         key: TK,
         val: TV,
-    ) {
-        let mut new_internal = InternalNode {
-            nibbles: SparseArray::with_capacity(2),
-            index,
+    ) -> *mut LeafNode<TK, TV> {
+        unsafe { (*trie).len += 1 };
+        let (t, nibble) = match position {
+            InsertPosition::Root => unsafe {
+                let leaf = LeafNode { key, val };
+                (*trie).root = Some(Node::Leaf(leaf));
+                return (*trie).root.as_mut().unwrap().as_mut_leaf();
+            },
+            InsertPosition::Child(t, nibble, ancestors) => {
+                let new_leaf = Node::Leaf(LeafNode { key, val });
+                unsafe { (*t).as_mut_internal().nibbles.set(nibble, new_leaf) };
+                ancestors.add(1);
+                ancestors.rescore(Self::score_fn(trie));
+                (t, nibble)
+            }
+            InsertPosition::Split(t, index, orig_nibble, ancestors) => {
+                let score = Self::score_fn(trie);
+                let nibble =
+                    Self::replace_with_internal_node(t, orig_nibble, index, key, val, score);
+                ancestors.add(1);
+                ancestors.rescore(score);
+                (t, nibble)
+            }
+            InsertPosition::Occupied(..) | InsertPosition::TooHigh => unsafe {
+                debug_unreachable!()
+            },
         };
-        let orig_nibble = Self::nibble(leaf.key.as_ref(), index);
-        let new_nibble = Self::nibble(key.as_ref(), index);
-        let new_leaf = Node::Leaf(LeafNode { key, val });
-        debug_assert!(orig_nibble != new_nibble);
-        let orig_node = unsafe { mem::replace(&mut *t, Node::Empty) };
-        new_internal.nibbles.set(orig_nibble, orig_node);
-        new_internal.nibbles.set(new_nibble, new_leaf);
-        unsafe { *t = Node::Internal(new_internal) };
+        // The new leaf is only borrowed once its ancestors are up to date.
+        unsafe {
+            (*t).as_mut_internal()
+                .nibbles
+                .get_mut(nibble)
+                .unwrap()
+                .as_mut_leaf()
+        }
+    }
+
+    /// Compares the nibbles of `a` and `b` in the `[from, to)` index range.
+    fn cmp_nibbles(a: &[u8], b: &[u8], from: usize, to: usize) -> Ordering {
+        (from..to)
+            .map(|index| Self::nibble(a, index).cmp(&Self::nibble(b, index)))
+            .find(|&ordering| ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    }
+
+    /// Classifies the subtree `t` with respect to the `lower` and `upper`
+    /// bounds, knowing that the keys of the subtree agree with both bounds on
+    /// the nibbles before `from`.
+    fn classify_range<'b>(
+        t: &Node<TK, TV>,
+        from: usize,
+        lower: Bound<&'b [u8]>,
+        upper: Bound<&'b [u8]>,
+    ) -> RangeClass<'b> {
+        let internal = match *t {
+            Node::Leaf(ref leaf) => {
+                let key = leaf.key.as_ref();
+                let above_lower = match lower {
+                    Bound::Included(bound) => key >= bound,
+                    Bound::Excluded(bound) => key > bound,
+                    Bound::Unbounded => true,
+                };
+                let below_upper = match upper {
+                    Bound::Included(bound) => key <= bound,
+                    Bound::Excluded(bound) => key < bound,
+                    Bound::Unbounded => true,
+                };
+                return if above_lower && below_upper {
+                    RangeClass::Inside
+                } else {
+                    RangeClass::Outside
+                };
+            }
+            Node::Internal(ref internal) => internal,
+            _ => unsafe { debug_unreachable!() },
+        };
+        if let (Bound::Unbounded, Bound::Unbounded) = (lower, upper) {
+            return RangeClass::Inside;
+        }
+        let rep_key = t.first_leaf().key.as_ref();
+        let lower = match lower {
+            Bound::Included(bound) | Bound::Excluded(bound) => {
+                match Self::cmp_nibbles(bound, rep_key, from, internal.index) {
+                    Ordering::Greater => return RangeClass::Outside,
+                    Ordering::Less => Bound::Unbounded,
+                    Ordering::Equal => lower,
+                }
+            }
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let upper = match upper {
+            Bound::Included(bound) | Bound::Excluded(bound) => {
+                match Self::cmp_nibbles(bound, rep_key, from, internal.index) {
+                    Ordering::Less => return RangeClass::Outside,
+                    Ordering::Greater => Bound::Unbounded,
+                    Ordering::Equal => upper,
+                }
+            }
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        match (lower, upper) {
+            (Bound::Unbounded, Bound::Unbounded) => RangeClass::Inside,
+            (lower, upper) => RangeClass::Partial(lower, upper),
+        }
+    }
+
+    /// Appends the largest subtrees of `t` whose keys are all within the
+    /// bounds to `todo`, in lexicographic order.
+    fn collect_range<'t>(
+        t: &'t Node<TK, TV>,
+        from: usize,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        todo: &mut VecDeque<&'t Node<TK, TV>>,
+    ) {
+        match Self::classify_range(t, from, lower, upper) {
+            RangeClass::Outside => {}
+            RangeClass::Inside => todo.push_back(t),
+            RangeClass::Partial(lower, upper) => {
+                let internal = t.as_internal();
+                for node in internal.nibbles.all() {
+                    Self::collect_range(node, internal.index, lower, upper, todo);
+                }
+            }
+        }
+    }
+
+    /// Appends the largest subtrees of `t` whose keys are all within the
+    /// bounds to `todo`, in lexicographic order.
+    fn collect_range_mut<'t>(
+        t: &'t mut Node<TK, TV>,
+        from: usize,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        todo: &mut VecDeque<&'t mut Node<TK, TV>>,
+    ) {
+        match Self::classify_range(t, from, lower, upper) {
+            RangeClass::Outside => {}
+            RangeClass::Inside => todo.push_back(t),
+            RangeClass::Partial(lower, upper) => {
+                let internal = t.as_mut_internal();
+                let index = internal.index;
+                for node in internal.nibbles.all_mut() {
+                    Self::collect_range_mut(node, index, lower, upper, todo);
+                }
+            }
+        }
+    }
+
+    fn range_bounds<R: KeyRange>(range: &R) -> (Bound<&[u8]>, Bound<&[u8]>) {
+        let (lower, upper) = (range.start_bound(), range.end_bound());
+        match (lower, upper) {
+            (Bound::Excluded(start), Bound::Excluded(end)) if start == end => {
+                panic!("range start and end are equal and excluded in Trie")
+            }
+            (Bound::Included(start), Bound::Included(end))
+            | (Bound::Included(start), Bound::Excluded(end))
+            | (Bound::Excluded(start), Bound::Included(end))
+            | (Bound::Excluded(start), Bound::Excluded(end))
+                if start > end =>
+            {
+                panic!("range start is greater than range end in Trie")
+            }
+            _ => {}
+        }
+        (lower, upper)
     }
 
     /// Creates a new, empty qp-trie.
@@ -158,192 +539,1524 @@ impl<TK: PartialEq + AsRef<[u8]>, TV> Trie<TK, TV> {
         self
     }
 
+    /// Ranks the entries with `score` in `prefix_top_k_scored()`.
+    ///
+    /// Internal nodes store the highest score below them. Insertions,
+    /// removals and splits keep it up to date, but handing out a mutable
+    /// reference to a value (`get_mut()`, `OccupiedEntry::into_mut()`,
+    /// `iter_mut()`...) leaves the scores stale until `rescore()` is called,
+    /// and so does merging a trie that is not scored by the same function.
+    #[cfg(feature = "subtree-scores")]
+    pub fn scored_by(mut self, score: fn(&TK, &TV) -> u64) -> Self {
+        self.score = Some(score);
+        self.rescore();
+        self
+    }
+
+    /// Recomputes the highest score of every subtree, after values have been
+    /// modified in place.
+    #[cfg(feature = "subtree-scores")]
+    pub fn rescore(&mut self) {
+        if let (Some(score), Some(root)) = (self.score, self.root.as_mut()) {
+            root.rescore_all(score);
+        }
+        self.scores_stale = false;
+    }
+
+    /// Returns the function scoring the entries of `trie`, if any.
+    #[cfg(feature = "subtree-scores")]
+    #[inline]
+    pub(crate) fn score_fn(trie: *const Self) -> Option<ScoreFn<TK, TV>> {
+        unsafe { (*trie).score }
+    }
+
+    /// Returns the function scoring the entries of `trie`, which is never
+    /// stored without the `subtree-scores` feature.
+    #[cfg(not(feature = "subtree-scores"))]
+    #[inline]
+    pub(crate) fn score_fn(_trie: *const Self) -> Option<ScoreFn<TK, TV>> {
+        None
+    }
+
+    /// Marks the highest scores of `trie` as stale, once a mutable reference
+    /// to a value has been handed out, and returns whether they already were.
+    ///
+    /// Only the flag is written, so that pointers derived from `trie` stay
+    /// valid.
+    #[cfg(feature = "subtree-scores")]
+    #[inline]
+    pub(crate) fn mark_scores_stale(trie: *mut Self) -> bool {
+        unsafe { mem::replace(&mut (*trie).scores_stale, true) }
+    }
+
+    /// Marks the highest scores of `trie` as stale, once a mutable reference
+    /// to a value has been handed out, and returns whether they already were.
+    #[cfg(not(feature = "subtree-scores"))]
+    #[inline]
+    pub(crate) fn mark_scores_stale(_trie: *mut Self) -> bool {
+        false
+    }
+
     /// Returns `true` if the trie is empty.
     #[inline]
     pub fn is_empty(&self) -> bool {
         self.root.is_none()
     }
 
+    /// Returns the number of keys stored in the trie.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Removes all the keys from the trie.
+    pub fn clear(&mut self) {
+        self.root = None;
+        self.len = 0;
+        #[cfg(feature = "subtree-scores")]
+        {
+            self.scores_stale = false;
+        }
+    }
+
     #[doc(hidden)]
     #[inline]
     pub fn root(&self) -> Option<&Node<TK, TV>> {
         self.root.as_ref()
     }
 
+    #[inline]
+    pub(crate) fn root_mut(&mut self) -> Option<&mut Node<TK, TV>> {
+        self.root.as_mut()
+    }
+
     /// Inserts a new node with the key `key`.
-    pub fn insert(&mut self, key: TK, val: TV) -> bool {
-        if self.root.is_none() {
-            let leaf = LeafNode { key, val };
-            self.root = Some(Node::Leaf(leaf));
-            return true;
-        }
-        let (leaf, height) = unsafe {
-            let closest = Self::find_closest_leaf_mut(self.root.as_mut().unwrap(), key.as_ref());
-            (&mut *closest.0, closest.1)
-        };
-        let leaf_key = &leaf.key;
-        let mut i = 0;
-        let mut x = 0;
-        let key_len = key.as_ref().len();
-        let leaf_key_len = leaf_key.as_ref().len();
-        let min_keys_len = cmp::min(key_len, leaf_key_len);
-        while i < min_keys_len {
-            x = key.as_ref()[i] ^ leaf_key.as_ref()[i];
-            if x != 0 {
-                break;
-            }
-            i += 1;
+    ///
+    /// Returns the value previously associated with the key, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the insertion would make the trie higher than its
+    /// `max_height`; use `try_insert()` to handle that case instead.
+    pub fn insert(&mut self, key: TK, val: TV) -> Option<TV> {
+        match self.try_insert(key, val) {
+            Ok(val) => val,
+            Err(e) => panic!("{}", e),
         }
-        if x == 0 {
-            if key_len == leaf_key_len {
-                leaf.val = val;
-                return false;
+    }
+
+    /// Inserts a new node with the key `key`.
+    ///
+    /// Returns the value previously associated with the key, if any, or an
+    /// error holding the key and the value if the insertion was refused.
+    pub fn try_insert(&mut self, key: TK, val: TV) -> Result<Option<TV>, InsertError<TK, TV>> {
+        let trie: *mut Self = self;
+        match Self::find_insert_position(trie, key.as_ref()) {
+            InsertPosition::Occupied(leaf, _, ancestors) => {
+                let previous = mem::replace(unsafe { &mut (*leaf).val }, val);
+                ancestors.rescore(Self::score_fn(trie));
+                Ok(Some(previous))
+            }
+            InsertPosition::TooHigh => Err(InsertError {
+                key,
+                val,
+                kind: InsertErrorKind::MaxHeight,
+            }),
+            position => {
+                Self::insert_at(trie, position, key, val);
+                Ok(None)
             }
-            x = 0xff;
-        }
-        let mut index = i * 2;
-        if (x & 0xf0) == 0 {
-            index += 1;
         }
-        let mut t: *mut Node<TK, TV> = self.root.as_mut().unwrap();
-        loop {
-            match *unsafe { &mut *t } {
-                Node::Leaf(ref leaf) => {
-                    if height >= self.max_height {
-                        return false;
-                    }
-                    Self::replace_leaf_with_internal_node(t, leaf, index, key, val);
-                    return true;
-                }
-                Node::Internal(ref mut internal) => {
-                    if internal.index <= index {
-                        let new_nibble = Self::nibble(key.as_ref(), internal.index);
-                        if let Some(t_next) = internal.nibbles.get_mut(new_nibble) {
-                            t = t_next;
-                            continue;
-                        }
-                        let new_leaf = Node::Leaf(LeafNode { key, val });
-                        internal.nibbles.set(new_nibble, new_leaf);
-                        return true;
-                    }
-                    if height >= self.max_height {
-                        return false;
-                    }
-                    Self::new_internal_for_shorter_index(t, leaf_key, index, key, val);
-                    return true;
-                }
-                _ => unsafe { debug_unreachable!() },
+    }
+
+    /// Gets the entry for the key `key`, for in-place manipulation.
+    ///
+    /// The trie is only traversed once, even if the key turns out to be
+    /// missing and a new value is inserted through the returned entry.
+    pub fn entry(&mut self, key: TK) -> Entry<'_, TK, TV> {
+        let trie: *mut Self = self;
+        match Self::find_insert_position(trie, key.as_ref()) {
+            InsertPosition::Occupied(leaf, parent, ancestors) => {
+                Entry::Occupied(OccupiedEntry::new(trie, leaf, parent, ancestors))
             }
+            position => Entry::Vacant(VacantEntry::new(trie, key, position)),
         }
     }
 
+    /// Returns `true` if the trie contains the key `key`.
+    pub fn contains_key<Q: ?Sized + AsRef<[u8]>>(&self, key: &Q) -> bool {
+        self.get(key).is_some()
+    }
+
     /// Returns the value associated with the key `key`, or `None` if the key is
     /// not present in the trie.
-    pub fn get(&self, key: &TK) -> Option<&TV> {
-        let root = match self.root.as_ref() {
-            None => return None,
-            Some(root) => root,
-        };
-        let leaf = match Self::find_exact_leaf(root, key.as_ref()) {
-            None => return None,
-            Some(leaf) => leaf,
-        };
+    ///
+    /// The key can be given as any type exposing its bytes, so that a trie
+    /// with owned keys can be queried using `&str` or `&[u8]`.
+    pub fn get<Q: ?Sized + AsRef<[u8]>>(&self, key: &Q) -> Option<&TV> {
+        let root = self.root.as_ref()?;
+        let leaf = Self::find_exact_leaf(root, key.as_ref())?;
         Some(&leaf.val)
     }
 
+    /// Returns the stored key and the value associated with the key `key`, or
+    /// `None` if the key is not present in the trie.
+    pub fn get_key_value<Q: ?Sized + AsRef<[u8]>>(&self, key: &Q) -> Option<(&TK, &TV)> {
+        let root = self.root.as_ref()?;
+        let leaf = Self::find_exact_leaf(root, key.as_ref())?;
+        Some((&leaf.key, &leaf.val))
+    }
+
     /// Returns a mutable value associated with the key `key`, or `None` if the
     /// key is not present in the trie.
-    pub fn get_mut(&mut self, key: &TK) -> Option<&mut TV> {
-        let root = match self.root.as_mut() {
-            None => return None,
-            Some(root) => root,
-        };
-        let leaf = match Self::find_exact_leaf_mut(root, key.as_ref()) {
-            None => return None,
-            Some(leaf) => leaf,
-        };
+    pub fn get_mut<Q: ?Sized + AsRef<[u8]>>(&mut self, key: &Q) -> Option<&mut TV> {
+        let trie: *mut Self = self;
+        let root = unsafe { (*trie).root.as_mut()? };
+        let leaf = Self::find_exact_leaf_mut(root, key.as_ref())?;
+        Self::mark_scores_stale(trie);
         Some(unsafe { &mut (*leaf).val })
     }
 
-    /// Removes the node associated with the key `key`.
-    ///
-    /// Returns `true` if the key was found, or `false` if the operation was a
-    /// no-op.
-    pub fn remove(&mut self, key: &TK) -> bool {
-        if self.root.is_none() {
-            return false;
-        }
-        let mut t: *mut Node<TK, TV> = self.root.as_mut().unwrap();
-        let (leaf, parent, nibble) = unsafe {
-            let mut parent = None;
-            let mut nibble = 0;
-            while let Node::Internal(ref mut internal) = *t {
-                let internal_index = internal.index;
-                nibble = Self::nibble(key.as_ref(), internal_index);
-                parent = Some(&mut *t);
-                t = match internal.nibbles.get_mut(nibble) {
-                    None => return false,
-                    Some(t) => t,
-                };
-            }
-            (&mut *t, parent, nibble)
-        };
-        if key.as_ref()[..] != leaf.as_leaf().key.as_ref()[..] {
-            return false;
-        }
-        let parent: &mut Node<TK, TV> = match parent {
-            None => {
-                self.root = None;
-                return true;
-            }
-            Some(parent) => parent,
-        };
-        parent.as_mut_internal().nibbles.remove(nibble);
-        debug_assert!(!parent.as_internal().nibbles.is_empty());
-        if parent.as_internal().nibbles.len() == 1 {
-            *parent = parent.as_mut_internal().nibbles.pop();
-        }
-        true
-    }
-
-    #[doc(hidden)]
-    pub fn prefix_find_next<'t>(
-        &self,
-        prefix: &TK,
-        todo: &mut Vec<&'t Node<TK, TV>>,
-        include_prefix: bool,
+    /// Walks down from `t` along `key`, and returns the next leaf whose key is
+    /// a prefix of `key`. Keys are found in increasing length order.
+    pub(crate) fn next_prefix_of<'t>(
+        t: &mut Option<&'t Node<TK, TV>>,
+        key: &[u8],
     ) -> Option<&'t LeafNode<TK, TV>> {
-        let prefix_len = prefix.as_ref().len();
-        while let Some(t) = todo.pop() {
-            match *t {
+        while let Some(node) = t.take() {
+            let internal = match *node {
                 Node::Leaf(ref leaf) => {
-                    if leaf.key.as_ref().starts_with(prefix.as_ref())
-                        && (include_prefix || leaf.key.as_ref() != prefix.as_ref())
-                    {
+                    if key.starts_with(leaf.key.as_ref()) {
                         return Some(leaf);
                     }
+                    return None;
                 }
-                Node::Internal(ref internal) => {
-                    if internal.index / 2 >= prefix_len {
-                        for node in internal.nibbles.all().iter().rev() {
-                            todo.push(node);
-                        }
-                    } else {
-                        let nibble = Self::nibble(prefix.as_ref(), internal.index);
-                        if let Some(node) = internal.nibbles.get(nibble) {
-                            todo.push(node)
-                        }
-                    }
+                Node::Internal(ref internal) => internal,
+                _ => unsafe { debug_unreachable!() },
+            };
+            let nibble = Self::nibble(key, internal.index);
+            *t = internal.nibbles.get(nibble);
+            if nibble == COMPLETE_KEY_NIBBLE {
+                continue;
+            }
+            if let Some(complete) = internal.nibbles.get(COMPLETE_KEY_NIBBLE) {
+                let leaf = complete.as_leaf();
+                if key.starts_with(leaf.key.as_ref()) {
+                    return Some(leaf);
                 }
-                _ => unreachable!(),
             }
         }
         None
     }
 
-    /// Creates a new iterator over all the nodes whose key includes `prefix` as
-    /// a prefix.
-    pub fn prefix_iter<'t>(&'t self, prefix: &'t TK) -> TriePrefixIterator<TK, TV> {
-        TriePrefixIterator::new(self, prefix, false)
+    /// Returns the leaf with the longest key that is a prefix of `key`.
+    fn longest_prefix_leaf(&self, key: &[u8]) -> Option<&LeafNode<TK, TV>> {
+        let mut t = self.root.as_ref();
+        let mut best = None;
+        while let Some(leaf) = Self::next_prefix_of(&mut t, key) {
+            best = Some(leaf);
+        }
+        best
+    }
+
+    /// Returns the leaves with the greatest key lower than `key`, with the
+    /// key `key` itself, and with the smallest key greater than `key`.
+    fn neighbours(&self, key: &[u8]) -> Neighbours<'_, TK, TV> {
+        let root = match self.root.as_ref() {
+            None => return (None, None, None),
+            Some(root) => root,
+        };
+        let (closest, _) = Self::find_closest_leaf(root, key);
+        let critical_index = Self::critical_index(key, closest.key.as_ref());
+        let (mut below, mut above) = (None, None);
+        let mut t = root;
+        while let Node::Internal(ref internal) = *t {
+            let index = internal.index;
+            if critical_index.is_some_and(|critical_index| index > critical_index) {
+                break;
+            }
+            let nibble = Self::nibble(key, index);
+            if let Some(node) = internal.nibbles.get_before(nibble) {
+                below = Some(node);
+            }
+            if let Some(node) = internal.nibbles.get_after(nibble) {
+                above = Some(node);
+            }
+            t = match internal.nibbles.get(nibble) {
+                None => {
+                    debug_assert_eq!(Some(index), critical_index);
+                    return (
+                        below.map(Node::last_leaf),
+                        None,
+                        above.map(Node::first_leaf),
+                    );
+                }
+                Some(t) => t,
+            };
+        }
+        match critical_index {
+            None => {
+                return (
+                    below.map(Node::last_leaf),
+                    Some(t.as_leaf()),
+                    above.map(Node::first_leaf),
+                )
+            }
+            Some(critical_index) => {
+                if Self::nibble(key, critical_index)
+                    < Self::nibble(closest.key.as_ref(), critical_index)
+                {
+                    above = Some(t);
+                } else {
+                    below = Some(t);
+                }
+            }
+        }
+        (
+            below.map(Node::last_leaf),
+            None,
+            above.map(Node::first_leaf),
+        )
+    }
+
+    /// Returns the entry with the greatest key less than or equal to `key`.
+    pub fn floor<Q: ?Sized + AsRef<[u8]>>(&self, key: &Q) -> Option<(&TK, &TV)> {
+        let (below, exact, _) = self.neighbours(key.as_ref());
+        exact.or(below).map(|leaf| (&leaf.key, &leaf.val))
+    }
+
+    /// Returns the entry with the smallest key greater than or equal to `key`.
+    pub fn ceiling<Q: ?Sized + AsRef<[u8]>>(&self, key: &Q) -> Option<(&TK, &TV)> {
+        let (_, exact, above) = self.neighbours(key.as_ref());
+        exact.or(above).map(|leaf| (&leaf.key, &leaf.val))
+    }
+
+    /// Returns the entry with the greatest key strictly less than `key`.
+    pub fn predecessor<Q: ?Sized + AsRef<[u8]>>(&self, key: &Q) -> Option<(&TK, &TV)> {
+        let (below, _, _) = self.neighbours(key.as_ref());
+        below.map(|leaf| (&leaf.key, &leaf.val))
+    }
+
+    /// Returns the entry with the smallest key strictly greater than `key`.
+    pub fn successor<Q: ?Sized + AsRef<[u8]>>(&self, key: &Q) -> Option<(&TK, &TV)> {
+        let (_, _, above) = self.neighbours(key.as_ref());
+        above.map(|leaf| (&leaf.key, &leaf.val))
+    }
+
+    /// Creates a new iterator over all the entries whose key is a prefix of
+    /// `key` (including `key` itself), shortest key first.
+    pub fn prefixes_of<'k, Q: ?Sized + AsRef<[u8]>>(
+        &self,
+        key: &'k Q,
+    ) -> PrefixesOf<'_, 'k, TK, TV> {
+        PrefixesOf::new(self.root.as_ref(), key.as_ref())
+    }
+
+    /// Creates a new iterator over all the entries whose key is within
+    /// `max_distance` insertions, deletions or substitutions of bytes from
+    /// `query`, along with their distance, in lexicographic order.
+    ///
+    /// Subtrees whose keys are all too far from the query are skipped.
+    pub fn fuzzy_iter<'q, Q: ?Sized + AsRef<[u8]>>(
+        &self,
+        query: &'q Q,
+        max_distance: usize,
+    ) -> FuzzyIter<'_, 'q, TK, TV> {
+        FuzzyIter::new(self, query.as_ref(), max_distance)
+    }
+
+    /// Creates a cursor before the entry with the smallest key.
+    pub fn cursor(&self) -> Cursor<'_, TK, TV> {
+        Cursor::new(self)
+    }
+
+    /// Creates a cursor before the entry with the smallest key, able to update
+    /// and remove entries, and to insert new entries next to it.
+    pub fn cursor_mut(&mut self) -> CursorMut<'_, TK, TV> {
+        CursorMut::new(self)
+    }
+
+    /// Creates a new iterator over all the entries whose key is accepted by
+    /// `automaton`, in lexicographic order.
+    ///
+    /// The automaton reads the bytes shared by all the keys of a subtree only
+    /// once, and subtrees are skipped as soon as it cannot match anymore, or
+    /// when they don't hold the bytes its `next_bytes()` expects.
+    pub fn search<A: Automaton>(&self, automaton: A) -> Search<'_, TK, TV, A> {
+        Search::new(self, automaton)
+    }
+
+    /// Creates a new iterator over all the entries whose key matches the glob
+    /// `pattern`, in lexicographic order.
+    ///
+    /// `?` matches any byte, `*` any sequence of bytes, `[abc]` and `[a-z]`
+    /// a byte from a class, `[!...]` or `[^...]` a byte outside a class, and
+    /// `\` escapes the next byte. Only the relevant children are visited where
+    /// the pattern is literal.
+    pub fn glob<Q: ?Sized + AsRef<[u8]>>(&self, pattern: &Q) -> GlobIter<'_, TK, TV> {
+        GlobIter::new(self, pattern.as_ref())
+    }
+
+    /// Returns the entry with the longest key that is a prefix of `key`
+    /// (including `key` itself), or `None` if no such key is present.
+    pub fn longest_prefix<Q: ?Sized + AsRef<[u8]>>(&self, key: &Q) -> Option<(&TK, &TV)> {
+        self.longest_prefix_leaf(key.as_ref())
+            .map(|leaf| (&leaf.key, &leaf.val))
+    }
+
+    /// Returns the entry with the longest key that is a prefix of `key`
+    /// (including `key` itself), with a mutable reference to its value.
+    pub fn longest_prefix_mut<Q: ?Sized + AsRef<[u8]>>(
+        &mut self,
+        key: &Q,
+    ) -> Option<(&TK, &mut TV)> {
+        let key = key.as_ref();
+        let prefix_len = self.longest_prefix_leaf(key)?.key.as_ref().len();
+        let trie: *mut Self = self;
+        let root = unsafe { (*trie).root.as_mut()? };
+        let leaf = Self::find_exact_leaf_mut(root, &key[..prefix_len])?;
+        Self::mark_scores_stale(trie);
+        unsafe { Some((&(*leaf).key, &mut (*leaf).val)) }
+    }
+
+    /// Removes the node associated with the key `key`.
+    ///
+    /// Returns the value that was associated with the key, or `None` if the
+    /// operation was a no-op.
+    pub fn remove<Q: ?Sized + AsRef<[u8]>>(&mut self, key: &Q) -> Option<TV> {
+        self.remove_entry(key).map(|(_, val)| val)
+    }
+
+    /// Removes the node associated with the key `key`.
+    ///
+    /// Returns the key and the value that were stored in the trie, or `None`
+    /// if the operation was a no-op.
+    pub fn remove_entry<Q: ?Sized + AsRef<[u8]>>(&mut self, key: &Q) -> Option<(TK, TV)> {
+        let trie: *mut Self = self;
+        let mut t: *mut Node<TK, TV> = unsafe { (*trie).root.as_mut()? };
+        let mut ancestors = Ancestors::new();
+        let parent = unsafe {
+            let mut parent = None;
+            while let Node::Internal(ref mut internal) = *t {
+                let internal_index = internal.index;
+                let nibble = Self::nibble(key.as_ref(), internal_index);
+                ancestors.push(t);
+                parent = Some((t, nibble));
+                t = internal.nibbles.get_mut(nibble)?;
+            }
+            if key.as_ref()[..] != (*t).as_leaf().key.as_ref()[..] {
+                return None;
+            }
+            parent
+        };
+        let leaf = Self::unlink_leaf(trie, parent, ancestors);
+        Some((leaf.key, leaf.val))
+    }
+
+    /// Detaches the leaf stored under `parent` from `trie`, and replaces the
+    /// parent with its last remaining child if there is only one left.
+    ///
+    /// `parent` and `ancestors` must have been derived from `trie`.
+    pub(crate) fn unlink_leaf(
+        trie: *mut Self,
+        parent: NodeParent<TK, TV>,
+        ancestors: Ancestors<TK, TV>,
+    ) -> LeafNode<TK, TV> {
+        unsafe { (*trie).len -= 1 };
+        ancestors.sub(1);
+        let leaf = Self::unlink_node(trie, parent).into_leaf();
+        ancestors.rescore(Self::score_fn(trie));
+        leaf
+    }
+
+    /// Detaches the subtree stored under `parent` from the trie, and replaces
+    /// the parent with its last remaining child if there is only one left.
+    ///
+    /// The number of keys is not updated.
+    fn unlink_node(trie: *mut Self, parent: NodeParent<TK, TV>) -> Node<TK, TV> {
+        let (parent, nibble) = match parent {
+            None => return unsafe { (*trie).root.take().unwrap() },
+            Some((parent, nibble)) => (unsafe { &mut *parent }, nibble),
+        };
+        let node = parent.as_mut_internal().nibbles.remove(nibble);
+        debug_assert!(!parent.as_internal().nibbles.is_empty());
+        if parent.as_internal().nibbles.len() == 1 {
+            *parent = parent.as_mut_internal().nibbles.pop();
+        }
+        node
+    }
+
+    /// Detaches the smallest subtree holding all the keys starting with
+    /// `prefix` from the trie, and returns it along with its number of keys.
+    fn unlink_prefix(&mut self, prefix: &[u8]) -> Option<(Node<TK, TV>, usize)> {
+        let trie: *mut Self = self;
+        let mut t: *mut Node<TK, TV> = unsafe { (*trie).root.as_mut()? };
+        let mut ancestors = Ancestors::new();
+        let mut parent = None;
+        let count = unsafe {
+            while let Node::Internal(ref mut internal) = *t {
+                if internal.index / 2 >= prefix.len() {
+                    break;
+                }
+                let nibble = Self::nibble(prefix, internal.index);
+                ancestors.push(t);
+                parent = Some((t, nibble));
+                t = internal.nibbles.get_mut(nibble)?;
+            }
+            if !(*t).first_leaf().key.as_ref().starts_with(prefix) {
+                return None;
+            }
+            (*t).count()
+        };
+        ancestors.sub(count);
+        let node = Self::unlink_node(trie, parent);
+        ancestors.rescore(Self::score_fn(trie));
+        unsafe { (*trie).len -= count };
+        Some((node, count))
+    }
+
+    /// Removes all the keys starting with `prefix`, and returns the number of
+    /// removed keys.
+    pub fn remove_prefix<Q: ?Sized + AsRef<[u8]>>(&mut self, prefix: &Q) -> usize {
+        self.unlink_prefix(prefix.as_ref())
+            .map_or(0, |(_, count)| count)
+    }
+
+    /// Moves all the keys starting with `prefix` to a new trie.
+    pub fn split_off_prefix<Q: ?Sized + AsRef<[u8]>>(&mut self, prefix: &Q) -> Self {
+        let (root, len) = match self.unlink_prefix(prefix.as_ref()) {
+            None => (None, 0),
+            Some((node, count)) => (Some(node), count),
+        };
+        Trie {
+            root,
+            max_height: self.max_height,
+            len,
+            #[cfg(feature = "subtree-scores")]
+            score: self.score,
+            #[cfg(feature = "subtree-scores")]
+            scores_stale: self.scores_stale,
+        }
+    }
+
+    /// Removes and returns the next entry of `trie` accepted by `pred`,
+    /// visiting the trie in lexicographic order.
+    ///
+    /// `frames` holds the path to the node being visited. The trie is kept
+    /// canonical after each removal, so that the visit can be stopped at any
+    /// point.
+    pub(crate) fn extract_next<F: FnMut(&TK, &mut TV) -> bool>(
+        trie: *mut Self,
+        frames: &mut Vec<ExtractFrame<TK, TV>>,
+        started: &mut bool,
+        pred: &mut F,
+    ) -> Option<(TK, TV)> {
+        if !*started {
+            *started = true;
+            let root: *mut Node<TK, TV> = unsafe { (*trie).root.as_mut()? };
+            match *unsafe { &mut *root } {
+                Node::Leaf(ref mut leaf) => {
+                    if !pred(&leaf.key, &mut leaf.val) {
+                        return None;
+                    }
+                    let leaf = Self::unlink_leaf(trie, None, Ancestors::new());
+                    return Some((leaf.key, leaf.val));
+                }
+                _ => frames.push(ExtractFrame {
+                    node: root,
+                    nibble: 0,
+                    next: 0,
+                }),
+            }
+        }
+        loop {
+            let (node, next) = match frames.last() {
+                None => return None,
+                Some(frame) => (frame.node, frame.next),
+            };
+            let internal = unsafe { (*node).as_mut_internal() };
+            let nibble = match internal.nibbles.next_index(next) {
+                None => {
+                    // All the children have been visited, and their values
+                    // possibly modified by `pred`.
+                    if let Some(score) = Self::score_fn(trie) {
+                        internal.rescore(score);
+                    }
+                    frames.pop();
+                    continue;
+                }
+                Some(nibble) => nibble,
+            };
+            frames.last_mut().unwrap().next = nibble + 1;
+            let child: *mut Node<TK, TV> = internal.nibbles.get_mut(nibble).unwrap();
+            match *unsafe { &mut *child } {
+                Node::Leaf(ref mut leaf) => {
+                    if !pred(&leaf.key, &mut leaf.val) {
+                        continue;
+                    }
+                }
+                Node::Internal(_) => {
+                    frames.push(ExtractFrame {
+                        node: child,
+                        nibble,
+                        next: 0,
+                    });
+                    continue;
+                }
+                _ => unsafe { debug_unreachable!() },
+            }
+            let leaf = Self::extract_leaf(trie, frames, started, nibble);
+            return Some((leaf.key, leaf.val));
+        }
+    }
+
+    /// Removes the leaf stored under `nibble` in the node on top of `frames`,
+    /// and replaces that node with its last remaining child if there is only
+    /// one left, updating `frames` so that the child still gets visited if it
+    /// hasn't been yet.
+    fn extract_leaf(
+        trie: *mut Self,
+        frames: &mut Vec<ExtractFrame<TK, TV>>,
+        started: &mut bool,
+        nibble: usize,
+    ) -> LeafNode<TK, TV> {
+        unsafe { (*trie).len -= 1 };
+        let mut ancestors = Ancestors::new();
+        for frame in frames.iter() {
+            ancestors.push(frame.node);
+        }
+        ancestors.sub(1);
+        let node = frames.last().unwrap().node;
+        let internal = unsafe { (*node).as_mut_internal() };
+        let leaf = internal.nibbles.remove(nibble).into_leaf();
+        if internal.nibbles.len() == 1 {
+            let frame = frames.pop().unwrap();
+            let visited = internal.nibbles.head_index() < nibble;
+            let child = internal.nibbles.pop();
+            let is_leaf = child.is_leaf();
+            unsafe { *node = child };
+            if !visited {
+                if !is_leaf {
+                    frames.push(ExtractFrame {
+                        node,
+                        nibble: frame.nibble,
+                        next: 0,
+                    });
+                } else if let Some(parent) = frames.last_mut() {
+                    parent.next = frame.nibble;
+                } else {
+                    *started = false;
+                }
+            }
+        }
+        leaf
+    }
+
+    /// Updates the highest score of the nodes whose visit by `extract_next()`
+    /// was left unfinished, from the bottom up, and restores the stale flag
+    /// of `trie` to `scores_stale`, every value modified by the predicate now
+    /// being accounted for.
+    #[cfg(feature = "subtree-scores")]
+    pub(crate) fn end_extract(
+        trie: *mut Self,
+        frames: &[ExtractFrame<TK, TV>],
+        scores_stale: bool,
+    ) {
+        if let Some(score) = Self::score_fn(trie) {
+            for frame in frames.iter().rev() {
+                unsafe { (*frame.node).as_mut_internal().rescore(score) };
+            }
+        }
+        unsafe { (*trie).scores_stale = scores_stale };
+    }
+
+    /// Updates the highest score of the nodes whose visit by `extract_next()`
+    /// was left unfinished, which are not tracked without the
+    /// `subtree-scores` feature.
+    #[cfg(not(feature = "subtree-scores"))]
+    #[inline]
+    pub(crate) fn end_extract(
+        _trie: *mut Self,
+        _frames: &[ExtractFrame<TK, TV>],
+        _scores_stale: bool,
+    ) {
+    }
+
+    /// Creates an iterator removing and returning the entries for which
+    /// `pred` returns `true`, in lexicographic order of the keys.
+    ///
+    /// The entries for which `pred` returns `false` are kept, and so are the
+    /// entries that haven't been visited yet if the iterator is dropped before
+    /// being fully consumed. The trie remains valid after each removal, even
+    /// if the iterator is leaked.
+    pub fn extract_if<F: FnMut(&TK, &mut TV) -> bool>(
+        &mut self,
+        pred: F,
+    ) -> ExtractIf<'_, TK, TV, F> {
+        ExtractIf::new(self, pred)
+    }
+
+    /// Only keeps the entries for which `f` returns `true`.
+    ///
+    /// The trie is traversed once, in lexicographic order of the keys.
+    pub fn retain<F: FnMut(&TK, &mut TV) -> bool>(&mut self, mut f: F) {
+        self.extract_if(|key, val| !f(key, val)).for_each(drop);
+    }
+
+    /// Builds a subtree out of the children of an internal node branching at
+    /// `index`, collapsing it if it has fewer than two children.
+    fn node_from_children(
+        index: usize,
+        mut nibbles: SparseArray<Node<TK, TV>>,
+        score: Option<ScoreFn<TK, TV>>,
+    ) -> Option<Node<TK, TV>> {
+        match nibbles.len() {
+            0 => None,
+            1 => Some(nibbles.pop()),
+            _ => Some(Node::Internal(InternalNode::new(index, nibbles, score))),
+        }
+    }
+
+    /// Splits the subtree `t` into the keys lower than `key`, and the keys
+    /// greater than or equal to `key`.
+    fn split_node(
+        t: Node<TK, TV>,
+        from: usize,
+        key: &[u8],
+        score: Option<ScoreFn<TK, TV>>,
+    ) -> SplitNodes<TK, TV> {
+        match Self::classify_range(&t, from, Bound::Included(key), Bound::Unbounded) {
+            RangeClass::Outside => (Some(t), None),
+            RangeClass::Inside => (None, Some(t)),
+            RangeClass::Partial(..) => {
+                let internal = match t {
+                    Node::Internal(internal) => internal,
+                    _ => unsafe { debug_unreachable!() },
+                };
+                let index = internal.index;
+                let (mut below, mut above) = (SparseArray::new(), SparseArray::new());
+                for (nibble, node) in internal.nibbles.into_entries() {
+                    let (node_below, node_above) = Self::split_node(node, index, key, score);
+                    if let Some(node) = node_below {
+                        below.set(nibble, node);
+                    }
+                    if let Some(node) = node_above {
+                        above.set(nibble, node);
+                    }
+                }
+                (
+                    Self::node_from_children(index, below, score),
+                    Self::node_from_children(index, above, score),
+                )
+            }
+        }
+    }
+
+    /// Moves all the keys greater than or equal to `key` to a new trie.
+    pub fn split_off<Q: ?Sized + AsRef<[u8]>>(&mut self, key: &Q) -> Self {
+        let mut other = Trie {
+            root: None,
+            max_height: self.max_height,
+            len: 0,
+            #[cfg(feature = "subtree-scores")]
+            score: self.score,
+            #[cfg(feature = "subtree-scores")]
+            scores_stale: self.scores_stale,
+        };
+        if let Some(root) = self.root.take() {
+            let (below, above) = Self::split_node(root, 0, key.as_ref(), Self::score_fn(self));
+            self.root = below;
+            other.root = above;
+            other.len = other.root.as_ref().map_or(0, Node::count);
+            self.len -= other.len;
+        }
+        other
+    }
+
+    /// Merges the subtrees `a` and `b`, calling `f` to combine the values of
+    /// the keys present in both, and counting these keys in `duplicates`.
+    ///
+    /// Subtrees whose keys do not overlap are moved as a whole.
+    fn merge_nodes<F: FnMut(&TK, TV, TV) -> TV>(
+        a: Node<TK, TV>,
+        b: Node<TK, TV>,
+        f: &mut F,
+        duplicates: &mut usize,
+        score: Option<ScoreFn<TK, TV>>,
+    ) -> Node<TK, TV> {
+        let (a_index, b_index) = (Self::branch_index(&a), Self::branch_index(&b));
+        let (a_key, b_key) = (a.first_leaf().key.as_ref(), b.first_leaf().key.as_ref());
+        if let Some(index) = Self::critical_index(a_key, b_key) {
+            if index < cmp::min(a_index, b_index) {
+                let (a_nibble, b_nibble) = (Self::nibble(a_key, index), Self::nibble(b_key, index));
+                let mut nibbles = SparseArray::with_capacity(2);
+                nibbles.set(a_nibble, a);
+                nibbles.set(b_nibble, b);
+                return Node::Internal(InternalNode::new(index, nibbles, score));
+            }
+        }
+        match (a, b) {
+            (Node::Leaf(a), Node::Leaf(b)) => {
+                *duplicates += 1;
+                let val = f(&a.key, a.val, b.val);
+                Node::Leaf(LeafNode { key: a.key, val })
+            }
+            (Node::Internal(mut a), b) if a_index < b_index => {
+                let nibble = Self::nibble(b.first_leaf().key.as_ref(), a_index);
+                match a.nibbles.get_mut(nibble) {
+                    Some(node) => {
+                        let a_node = mem::replace(node, Node::Empty);
+                        *node = Self::merge_nodes(a_node, b, f, duplicates, score);
+                    }
+                    None => {
+                        a.nibbles.set(nibble, b);
+                    }
+                }
+                a.recount();
+                if let Some(score) = score {
+                    a.rescore(score);
+                }
+                Node::Internal(a)
+            }
+            (a, Node::Internal(mut b)) if b_index < a_index => {
+                let nibble = Self::nibble(a.first_leaf().key.as_ref(), b_index);
+                match b.nibbles.get_mut(nibble) {
+                    Some(node) => {
+                        let b_node = mem::replace(node, Node::Empty);
+                        *node = Self::merge_nodes(a, b_node, f, duplicates, score);
+                    }
+                    None => {
+                        b.nibbles.set(nibble, a);
+                    }
+                }
+                b.recount();
+                if let Some(score) = score {
+                    b.rescore(score);
+                }
+                Node::Internal(b)
+            }
+            (Node::Internal(mut a), Node::Internal(b)) => {
+                debug_assert_eq!(a_index, b_index);
+                for (nibble, b_node) in b.nibbles.into_entries() {
+                    match a.nibbles.get_mut(nibble) {
+                        Some(node) => {
+                            let a_node = mem::replace(node, Node::Empty);
+                            *node = Self::merge_nodes(a_node, b_node, f, duplicates, score);
+                        }
+                        None => {
+                            a.nibbles.set(nibble, b_node);
+                        }
+                    }
+                }
+                a.recount();
+                if let Some(score) = score {
+                    a.rescore(score);
+                }
+                Node::Internal(a)
+            }
+            _ => unsafe { debug_unreachable!() },
+        }
+    }
+
+    /// Returns the number of internal nodes on the longest path from `t` to a
+    /// leaf.
+    fn height(t: &Node<TK, TV>) -> usize {
+        match *t {
+            Node::Internal(ref internal) => {
+                1 + internal
+                    .nibbles
+                    .all()
+                    .iter()
+                    .map(Self::height)
+                    .max()
+                    .unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Returns the height of the subtree `merge_nodes()` would build out of
+    /// `a` and `b`, without modifying them.
+    fn merged_height(a: &Node<TK, TV>, b: &Node<TK, TV>) -> usize {
+        let (a_index, b_index) = (Self::branch_index(a), Self::branch_index(b));
+        let (a_key, b_key) = (a.first_leaf().key.as_ref(), b.first_leaf().key.as_ref());
+        if let Some(index) = Self::critical_index(a_key, b_key) {
+            if index < cmp::min(a_index, b_index) {
+                return 1 + cmp::max(Self::height(a), Self::height(b));
+            }
+        }
+        match (a, b) {
+            (Node::Leaf(_), Node::Leaf(_)) => 0,
+            (Node::Internal(a), b) if a_index < b_index => Self::merged_child_height(a, b),
+            (a, Node::Internal(b)) if b_index < a_index => Self::merged_child_height(b, a),
+            (Node::Internal(a), Node::Internal(b)) => {
+                let a_heights =
+                    a.nibbles
+                        .entries()
+                        .map(|(nibble, a_node)| match b.nibbles.get(nibble) {
+                            Some(b_node) => Self::merged_height(a_node, b_node),
+                            None => Self::height(a_node),
+                        });
+                let b_heights = b
+                    .nibbles
+                    .entries()
+                    .filter(|&(nibble, _)| !a.nibbles.has_sparse_index(nibble))
+                    .map(|(_, b_node)| Self::height(b_node));
+                1 + a_heights.chain(b_heights).max().unwrap_or(0)
+            }
+            _ => unsafe { debug_unreachable!() },
+        }
+    }
+
+    /// Returns the height of the subtree `merge_nodes()` would build out of
+    /// `a` and `b`, if `b` goes below one of the children of `a`.
+    fn merged_child_height(a: &InternalNode<TK, TV>, b: &Node<TK, TV>) -> usize {
+        let nibble = Self::nibble(b.first_leaf().key.as_ref(), a.index);
+        let heights = a.nibbles.entries().map(|(a_nibble, a_node)| {
+            if a_nibble == nibble {
+                Self::merged_height(a_node, b)
+            } else {
+                Self::height(a_node)
+            }
+        });
+        let b_height = if a.nibbles.has_sparse_index(nibble) {
+            0
+        } else {
+            Self::height(b)
+        };
+        1 + heights.fold(b_height, cmp::max)
+    }
+
+    /// Moves all the entries of `other` into the trie, calling `f` with the
+    /// key and both values to compute the value of keys present in both.
+    ///
+    /// Subtrees of both tries that do not overlap are moved as a whole
+    /// instead of being inserted key by key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the merged trie would be higher than the `max_height` of
+    /// this trie; use `try_merge_with()` to handle that case instead.
+    pub fn merge_with<F: FnMut(&TK, TV, TV) -> TV>(&mut self, other: Self, f: F) {
+        if self.try_merge_with(other, f).is_err() {
+            panic!("merge refused: {}", InsertErrorKind::MaxHeight);
+        }
+    }
+
+    /// Moves all the entries of `other` into the trie, calling `f` with the
+    /// key and both values to compute the value of keys present in both.
+    ///
+    /// If the merged trie would be higher than the `max_height` of this trie,
+    /// both tries are left untouched, and `other` is handed back as an error.
+    pub fn try_merge_with<F: FnMut(&TK, TV, TV) -> TV>(
+        &mut self,
+        other: Self,
+        mut f: F,
+    ) -> Result<(), Self> {
+        let other_root = match other.root.as_ref() {
+            None => return Ok(()),
+            Some(other_root) => other_root,
+        };
+        if self.max_height != usize::MAX {
+            let height = match self.root.as_ref() {
+                None => Self::height(other_root),
+                Some(root) => Self::merged_height(root, other_root),
+            };
+            if height > self.max_height {
+                return Err(other);
+            }
+        }
+        #[cfg(feature = "subtree-scores")]
+        if let Some(score) = self.score {
+            let same_score = other
+                .score
+                .is_some_and(|other_score| ptr::fn_addr_eq(score, other_score));
+            if !same_score || other.scores_stale {
+                self.scores_stale = true;
+            }
+        }
+        let other_root = other.root.unwrap();
+        let mut duplicates = 0;
+        self.root = Some(match self.root.take() {
+            None => other_root,
+            Some(root) => Self::merge_nodes(
+                root,
+                other_root,
+                &mut f,
+                &mut duplicates,
+                Self::score_fn(self),
+            ),
+        });
+        self.len += other.len - duplicates;
+        Ok(())
+    }
+
+    /// Moves all the entries of `other` into the trie, leaving `other` empty.
+    ///
+    /// Values of keys present in both tries are replaced with the values from
+    /// `other`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the merged trie would be higher than the `max_height` of
+    /// this trie, in which case neither trie is modified.
+    pub fn append(&mut self, other: &mut Self) {
+        let taken = mem::replace(
+            other,
+            Trie {
+                root: None,
+                max_height: other.max_height,
+                len: 0,
+                #[cfg(feature = "subtree-scores")]
+                score: other.score,
+                #[cfg(feature = "subtree-scores")]
+                scores_stale: false,
+            },
+        );
+        if let Err(taken) = self.try_merge_with(taken, |_, _, val| val) {
+            *other = taken;
+            panic!("merge refused: {}", InsertErrorKind::MaxHeight);
+        }
+    }
+
+    /// Returns the index at which `t` branches, or `usize::MAX` for a leaf.
+    #[inline]
+    fn branch_index(t: &Node<TK, TV>) -> usize {
+        match *t {
+            Node::Internal(ref internal) => internal.index,
+            _ => usize::MAX,
+        }
+    }
+
+    /// Compares the subtrees `a` and `b`, which may belong to different tries.
+    pub(crate) fn relate_subtrees(a: &Node<TK, TV>, b: &Node<TK, TV>) -> SubtreeRelation {
+        let (a_index, b_index) = (Self::branch_index(a), Self::branch_index(b));
+        let (a_key, b_key) = (a.first_leaf().key.as_ref(), b.first_leaf().key.as_ref());
+        match Self::critical_index(a_key, b_key) {
+            Some(index) if index < cmp::min(a_index, b_index) => {
+                if Self::nibble(a_key, index) < Self::nibble(b_key, index) {
+                    SubtreeRelation::Before
+                } else {
+                    SubtreeRelation::After
+                }
+            }
+            _ => match a_index.cmp(&b_index) {
+                Ordering::Less => SubtreeRelation::Contains,
+                Ordering::Greater => SubtreeRelation::ContainedIn,
+                Ordering::Equal if a_index == usize::MAX => SubtreeRelation::Same,
+                Ordering::Equal => SubtreeRelation::Aligned,
+            },
+        }
+    }
+
+    /// Returns the `k` entries with the highest score among the keys
+    /// starting with `prefix`, including `prefix` itself, best first.
+    ///
+    /// Entries are scored by `score_fn`, and entries with the same score are
+    /// returned in lexicographic order of their keys. Every entry under the
+    /// prefix is scored, but only `k` of them are kept in memory.
+    pub fn prefix_top_k<Q: ?Sized + AsRef<[u8]>, F: FnMut(&TK, &TV) -> u64>(
+        &self,
+        prefix: &Q,
+        k: usize,
+        score_fn: F,
+    ) -> Vec<(&TK, &TV)> {
+        if k == 0 {
+            return vec![];
+        }
+        Self::scan_top_k(self.prefix_iter(prefix).include_prefix(), k, score_fn)
+    }
+
+    /// Returns the `k` entries with the highest score among the keys
+    /// starting with `prefix`, as scored by the function given to
+    /// `scored_by()`, or `None` if the trie has no scoring function.
+    ///
+    /// The subtrees are explored best first, and the ones whose highest score
+    /// cannot beat the `k`-th best entry are skipped. If the scores are stale,
+    /// every entry under the prefix is scored, as with `prefix_top_k()`.
+    #[cfg(feature = "subtree-scores")]
+    pub fn prefix_top_k_scored<Q: ?Sized + AsRef<[u8]>>(
+        &self,
+        prefix: &Q,
+        k: usize,
+    ) -> Option<Vec<(&TK, &TV)>> {
+        let score = self.score?;
+        if self.scores_stale {
+            return Some(self.prefix_top_k(prefix, k, score));
+        }
+        let mut top = vec![];
+        let mut candidates = BinaryHeap::new();
+        if let Some(t) = self.prefix_root(prefix.as_ref()) {
+            candidates.push(Self::scored_node(t, score));
+        }
+        while top.len() < k {
+            let best = match candidates.pop() {
+                None => break,
+                Some(best) => best,
+            };
+            match *best.item {
+                Node::Leaf(ref leaf) => top.push((&leaf.key, &leaf.val)),
+                Node::Internal(ref internal) => candidates.extend(
+                    internal
+                        .nibbles
+                        .all()
+                        .iter()
+                        .map(|child| Self::scored_node(child, score)),
+                ),
+                _ => unsafe { debug_unreachable!() },
+            }
+        }
+        Some(top)
+    }
+
+    /// Returns the subtree `t` as a candidate for `prefix_top_k_scored()`.
+    #[cfg(feature = "subtree-scores")]
+    fn scored_node(t: &Node<TK, TV>, score: ScoreFn<TK, TV>) -> Scored<'_, &Node<TK, TV>> {
+        Scored {
+            score: t.max_score(score),
+            key: t.first_leaf().key.as_ref(),
+            item: t,
+        }
+    }
+
+    /// Scores all the entries of `entries`, and returns the `k` best ones,
+    /// keeping only `k` of them in memory.
+    fn scan_top_k<'t, I: Iterator<Item = (&'t TK, &'t TV)>, F: FnMut(&TK, &TV) -> u64>(
+        entries: I,
+        k: usize,
+        mut score: F,
+    ) -> Vec<(&'t TK, &'t TV)> {
+        let mut best = BinaryHeap::new();
+        for entry in entries {
+            let candidate = Scored {
+                score: score(entry.0, entry.1),
+                key: entry.0.as_ref(),
+                item: entry,
+            };
+            if best.len() < k {
+                best.push(Reverse(candidate));
+            } else if best.peek().is_some_and(|worst| candidate > worst.0) {
+                best.pop();
+                best.push(Reverse(candidate));
+            }
+        }
+        best.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(candidate)| candidate.item)
+            .collect()
+    }
+
+    /// Returns an iterator over the entries whose key is in the trie or in
+    /// `other`, in lexicographic order.
+    ///
+    /// The entries of `self` are returned for keys present in both tries.
+    pub fn union<'t>(&'t self, other: &'t Self) -> Union<'t, TK, TV> {
+        Union::new(self, other)
+    }
+
+    /// Returns an iterator over the entries of the trie whose key is also in
+    /// `other`, in lexicographic order.
+    pub fn intersection<'t>(&'t self, other: &'t Self) -> Intersection<'t, TK, TV> {
+        Intersection::new(self, other)
+    }
+
+    /// Returns an iterator over the entries of the trie whose key is not in
+    /// `other`, in lexicographic order.
+    pub fn difference<'t>(&'t self, other: &'t Self) -> Difference<'t, TK, TV> {
+        Difference::new(self, other)
+    }
+
+    /// Returns an iterator over the entries whose key is either in the trie or
+    /// in `other`, but not in both, in lexicographic order.
+    pub fn symmetric_difference<'t>(&'t self, other: &'t Self) -> SymmetricDifference<'t, TK, TV> {
+        SymmetricDifference::new(self, other)
+    }
+
+    /// Returns the location of the leaf of `trie` with the smallest key, or
+    /// with the greatest key if `last` is `true`, along with its ancestors.
+    fn find_edge_leaf_mut(
+        trie: *mut Self,
+        last: bool,
+    ) -> Option<(NodeParent<TK, TV>, Ancestors<TK, TV>)> {
+        let mut t: *mut Node<TK, TV> = unsafe { (*trie).root.as_mut()? };
+        let mut ancestors = Ancestors::new();
+        let mut parent = None;
+        unsafe {
+            while let Node::Internal(ref mut internal) = *t {
+                let nibble = if last {
+                    internal.nibbles.tail_index()
+                } else {
+                    internal.nibbles.head_index()
+                };
+                ancestors.push(t);
+                parent = Some((t, nibble));
+                t = internal.nibbles.get_mut(nibble).unwrap();
+            }
+        }
+        Some((parent, ancestors))
+    }
+
+    /// Returns the number of keys starting with `prefix`.
+    ///
+    /// With the `subtree-counts` feature, this runs in time proportional to
+    /// the height of the trie. Otherwise, the matching keys are counted one by
+    /// one.
+    pub fn count_prefix<Q: ?Sized + AsRef<[u8]>>(&self, prefix: &Q) -> usize {
+        self.prefix_root(prefix.as_ref()).map_or(0, Node::count)
+    }
+
+    /// Returns the number of keys lower than `key`, which is the position of
+    /// `key` in lexicographic order if it is present in the trie.
+    ///
+    /// With the `subtree-counts` feature, this runs in time proportional to
+    /// the height of the trie. Otherwise, the lower keys are counted one by
+    /// one.
+    pub fn rank<Q: ?Sized + AsRef<[u8]>>(&self, key: &Q) -> usize {
+        let key = key.as_ref();
+        let root = match self.root.as_ref() {
+            None => return 0,
+            Some(root) => root,
+        };
+        let (closest, _) = Self::find_closest_leaf(root, key);
+        let closest_key = closest.key.as_ref();
+        let critical = Self::critical_index(key, closest_key).unwrap_or(usize::MAX);
+        let mut rank = 0;
+        let mut t = root;
+        while let Node::Internal(ref internal) = *t {
+            if internal.index > critical {
+                break;
+            }
+            let nibble = Self::nibble(key, internal.index);
+            rank += internal
+                .nibbles
+                .entries()
+                .take_while(|&(child_nibble, _)| child_nibble < nibble)
+                .map(|(_, child)| child.count())
+                .sum::<usize>();
+            match internal.nibbles.get(nibble) {
+                None => return rank,
+                Some(t_next) => t = t_next,
+            }
+        }
+        // All the keys of `t` are either lower or greater than `key`, unless
+        // `t` is the leaf holding `key`.
+        if critical != usize::MAX
+            && Self::nibble(key, critical) > Self::nibble(closest_key, critical)
+        {
+            rank += t.count();
+        }
+        rank
+    }
+
+    /// Returns the entry at position `position` in lexicographic order, or
+    /// `None` if the trie has fewer keys.
+    ///
+    /// With the `subtree-counts` feature, this runs in time proportional to
+    /// the height of the trie. Otherwise, the entries are iterated over until
+    /// the requested one.
+    pub fn select(&self, position: usize) -> Option<(&TK, &TV)> {
+        if position >= self.len {
+            return None;
+        }
+        if !cfg!(feature = "subtree-counts") {
+            return self.iter().nth(position);
+        }
+        let mut remaining = position;
+        let mut t = self.root.as_ref()?;
+        while let Node::Internal(ref internal) = *t {
+            for child in internal.nibbles.all() {
+                let count = child.count();
+                if remaining < count {
+                    t = child;
+                    break;
+                }
+                remaining -= count;
+            }
+        }
+        let leaf = t.as_leaf();
+        Some((&leaf.key, &leaf.val))
+    }
+
+    /// Returns the entry with the smallest key, or `None` if the trie is
+    /// empty.
+    pub fn first_key_value(&self) -> Option<(&TK, &TV)> {
+        let leaf = self.root.as_ref()?.first_leaf();
+        Some((&leaf.key, &leaf.val))
+    }
+
+    /// Returns the entry with the greatest key, or `None` if the trie is
+    /// empty.
+    pub fn last_key_value(&self) -> Option<(&TK, &TV)> {
+        let leaf = self.root.as_ref()?.last_leaf();
+        Some((&leaf.key, &leaf.val))
+    }
+
+    /// Removes the entry with the smallest key, and returns it.
+    pub fn pop_first(&mut self) -> Option<(TK, TV)> {
+        let trie: *mut Self = self;
+        let (parent, ancestors) = Self::find_edge_leaf_mut(trie, false)?;
+        let leaf = Self::unlink_leaf(trie, parent, ancestors);
+        Some((leaf.key, leaf.val))
+    }
+
+    /// Removes the entry with the greatest key, and returns it.
+    pub fn pop_last(&mut self) -> Option<(TK, TV)> {
+        let trie: *mut Self = self;
+        let (parent, ancestors) = Self::find_edge_leaf_mut(trie, true)?;
+        let leaf = Self::unlink_leaf(trie, parent, ancestors);
+        Some((leaf.key, leaf.val))
+    }
+
+    /// Returns the smallest subtree holding all the keys starting with
+    /// `prefix`, or `None` if there are no such keys.
+    pub(crate) fn prefix_root(&self, prefix: &[u8]) -> Option<&Node<TK, TV>> {
+        let mut t = self.root.as_ref()?;
+        while let Node::Internal(ref internal) = *t {
+            if internal.index / 2 >= prefix.len() {
+                break;
+            }
+            t = internal.nibbles.get(Self::nibble(prefix, internal.index))?;
+        }
+        if !t.first_leaf().key.as_ref().starts_with(prefix) {
+            return None;
+        }
+        Some(t)
+    }
+
+    /// Returns the smallest subtree holding all the keys starting with
+    /// `prefix`, or `None` if there are no such keys.
+    pub(crate) fn prefix_root_mut(&mut self, prefix: &[u8]) -> Option<&mut Node<TK, TV>> {
+        let mut t = self.root.as_mut()?;
+        loop {
+            let index = match *t {
+                Node::Internal(ref internal) if internal.index / 2 < prefix.len() => internal.index,
+                _ => break,
+            };
+            t = t
+                .as_mut_internal()
+                .nibbles
+                .get_mut(Self::nibble(prefix, index))?;
+        }
+        if !t.first_leaf().key.as_ref().starts_with(prefix) {
+            return None;
+        }
+        Some(t)
+    }
+
+    /// Creates a new iterator over all the nodes whose key includes `prefix` as
+    /// a prefix.
+    pub fn prefix_iter<Q: ?Sized + AsRef<[u8]>>(
+        &self,
+        prefix: &Q,
+    ) -> TriePrefixIterator<'_, TK, TV> {
+        TriePrefixIterator::new(self, prefix, false)
+    }
+
+    /// Creates a new iterator over all the nodes whose key includes `prefix` as
+    /// a prefix, with mutable references to the values.
+    pub fn prefix_iter_mut<Q: ?Sized + AsRef<[u8]>>(
+        &mut self,
+        prefix: &Q,
+    ) -> TriePrefixIteratorMut<'_, TK, TV> {
+        if self.prefix_root(prefix.as_ref()).is_some() {
+            Self::mark_scores_stale(self);
+        }
+        TriePrefixIteratorMut::new(self, prefix, false)
+    }
+
+    /// Creates a new iterator over all the entries of the trie, in
+    /// lexicographic order of the keys.
+    pub fn iter(&self) -> Iter<'_, TK, TV> {
+        Iter::new(self)
+    }
+
+    /// Creates a new iterator over all the entries of the trie, with mutable
+    /// references to the values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, TK, TV> {
+        if !self.is_empty() {
+            Self::mark_scores_stale(self);
+        }
+        IterMut::new(self)
+    }
+
+    /// Creates a new iterator over all the keys of the trie, in lexicographic
+    /// order.
+    pub fn keys(&self) -> Keys<'_, TK, TV> {
+        Keys::new(self)
+    }
+
+    /// Creates a new iterator over all the values of the trie, in
+    /// lexicographic order of the keys.
+    pub fn values(&self) -> Values<'_, TK, TV> {
+        Values::new(self)
+    }
+
+    /// Creates a new iterator over mutable references to all the values of
+    /// the trie, in lexicographic order of the keys.
+    pub fn values_mut(&mut self) -> ValuesMut<'_, TK, TV> {
+        if !self.is_empty() {
+            Self::mark_scores_stale(self);
+        }
+        ValuesMut::new(self)
+    }
+
+    /// Removes all the entries from the trie, and returns them as an iterator,
+    /// in lexicographic order of the keys.
+    pub fn drain(&mut self) -> Drain<'_, TK, TV> {
+        let len = mem::replace(&mut self.len, 0);
+        Drain::new(IntoIter::new(self.root.take(), len))
+    }
+
+    /// Creates a new iterator over the entries whose key is within `range`,
+    /// in lexicographic order.
+    ///
+    /// `range` can be any standard range over keys exposing their bytes, such
+    /// as `"a".."b"`, `&b"a"[..]..` or `..`. This takes a `KeyRange` rather
+    /// than a `RangeBounds<[u8]>`, because the standard library only
+    /// implements the latter for `..` and for pairs of `Bound<&[u8]>`: `a..b`
+    /// with `a` and `b` of type `&[u8]` would be rejected. A `RangeBounds<Q>`
+    /// with a generic `Q` would accept it, but would leave `Q` unknown for a
+    /// full range, which would then have to be written `range::<[u8], _>(..)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the range is greater than its end, or if both
+    /// bounds are equal and excluded.
+    pub fn range<R: KeyRange>(&self, range: R) -> Range<'_, TK, TV> {
+        let (lower, upper) = Self::range_bounds(&range);
+        let mut todo = VecDeque::new();
+        if let Some(root) = self.root.as_ref() {
+            Self::collect_range(root, 0, lower, upper, &mut todo);
+        }
+        Range::new(todo, self.len)
+    }
+
+    /// Creates a new iterator over the entries whose key is within `range`,
+    /// with mutable references to the values.
+    ///
+    /// `range` is given as with `range()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the range is greater than its end, or if both
+    /// bounds are equal and excluded.
+    pub fn range_mut<R: KeyRange>(&mut self, range: R) -> RangeMut<'_, TK, TV> {
+        let (lower, upper) = Self::range_bounds(&range);
+        let mut todo = VecDeque::new();
+        let trie: *mut Self = self;
+        if let Some(root) = unsafe { (*trie).root.as_mut() } {
+            Self::collect_range_mut(root, 0, lower, upper, &mut todo);
+        }
+        if !todo.is_empty() {
+            Self::mark_scores_stale(trie);
+        }
+        RangeMut::new(todo, self.len)
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> IntoIterator for Trie<TK, TV> {
+    type Item = (TK, TV);
+    type IntoIter = IntoIter<TK, TV>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self.root, self.len)
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> IntoIterator for &'t Trie<TK, TV> {
+    type Item = (&'t TK, &'t TV);
+    type IntoIter = Iter<'t, TK, TV>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'t, TK: PartialEq + AsRef<[u8]>, TV> IntoIterator for &'t mut Trie<TK, TV> {
+    type Item = (&'t TK, &'t mut TV);
+    type IntoIter = IterMut<'t, TK, TV>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> FromIterator<(TK, TV)> for Trie<TK, TV> {
+    fn from_iter<I: IntoIterator<Item = (TK, TV)>>(iter: I) -> Self {
+        let mut trie = Trie::new();
+        trie.extend(iter);
+        trie
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> Extend<(TK, TV)> for Trie<TK, TV> {
+    fn extend<I: IntoIterator<Item = (TK, TV)>>(&mut self, iter: I) {
+        for (key, val) in iter {
+            self.insert(key, val);
+        }
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV> From<BTreeMap<TK, TV>> for Trie<TK, TV> {
+    fn from(map: BTreeMap<TK, TV>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV, const N: usize> From<[(TK, TV); N]> for Trie<TK, TV> {
+    fn from(pairs: [(TK, TV); N]) -> Self {
+        IntoIterator::into_iter(pairs).collect()
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV, Q: ?Sized + AsRef<[u8]>> Index<&Q> for Trie<TK, TV> {
+    type Output = TV;
+
+    /// Returns a reference to the value associated with `key`.
+    ///
+    /// Panics if the key is not present in the trie.
+    #[inline]
+    fn index(&self, key: &Q) -> &TV {
+        self.get(key).expect("key not found")
+    }
+}
+
+/// Tries are compared by their ordered contents, keys being compared as byte
+/// strings, regardless of their internal shape and maximum height.
+impl<TK: PartialEq + AsRef<[u8]>, TV: PartialEq> PartialEq for Trie<TK, TV> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len
+            && self
+                .iter()
+                .zip(other.iter())
+                .all(|((k1, v1), (k2, v2))| k1.as_ref() == k2.as_ref() && v1 == v2)
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV: Eq> Eq for Trie<TK, TV> {}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV: PartialOrd> PartialOrd for Trie<TK, TV> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter()
+            .map(|(key, val)| (key.as_ref(), val))
+            .partial_cmp(other.iter().map(|(key, val)| (key.as_ref(), val)))
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV: Ord> Ord for Trie<TK, TV> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter()
+            .map(|(key, val)| (key.as_ref(), val))
+            .cmp(other.iter().map(|(key, val)| (key.as_ref(), val)))
+    }
+}
+
+impl<TK: PartialEq + AsRef<[u8]>, TV: Hash> Hash for Trie<TK, TV> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len);
+        for (key, val) in self {
+            key.as_ref().hash(state);
+            val.hash(state);
+        }
     }
 }